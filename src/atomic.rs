@@ -0,0 +1,149 @@
+//! Writes the TOML files the lifecycle reads, so that a crash mid-write leaves either the old file
+//! or the new one, never a truncated one.
+//!
+//! Contents go to a temp file in the same directory, which is fsynced and then renamed over the
+//! destination. Don't use it for files in `profile.d`, `exec.d` or the env dirs: the lifecycle
+//! reads every file in those, so a temp file left behind by a crash would be picked up too.
+
+use crate::error::{Operation, Result, ResultExt};
+use serde::Serialize;
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// makes temp file names unique between concurrent writes in the same process
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    let path = path.as_ref();
+
+    replace(path, |file| file.write_all(contents.as_ref())).with_context(Operation::Write, path)
+}
+
+pub(crate) fn write_toml<P: AsRef<Path>, T: Serialize + ?Sized>(path: P, value: &T) -> Result<()> {
+    let path = path.as_ref();
+    let toml_string = toml::to_string(value).with_context(Operation::Serialize, path)?;
+
+    write(path, toml_string)
+}
+
+fn replace<F>(path: &Path, fill: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let tmp_path = tmp_path(path);
+    let result = (|| {
+        let mut file = File::create(&tmp_path)?;
+        fill(&mut file)?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    } else {
+        // persist the rename itself, best effort as not every filesystem supports it
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    result
+}
+
+// hidden, and unique per call so concurrent writers don't clobber each other's temp files
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Error;
+    use std::result::Result;
+    use tempdir::TempDir;
+
+    #[test]
+    fn it_replaces_files() -> Result<(), Error> {
+        let tmpdir = TempDir::new("atomic")?;
+        let path = tmpdir.path().join("launch.toml");
+        fs::write(&path, "old contents that are longer")?;
+
+        write(&path, "new")?;
+
+        assert_eq!(fs::read_to_string(&path)?, "new");
+        assert_eq!(fs::read_dir(tmpdir.path())?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_toml() -> Result<(), Error> {
+        let tmpdir = TempDir::new("atomic")?;
+        let path = tmpdir.path().join("plan.toml");
+        let mut value = toml::value::Table::new();
+        value.insert("ruby".to_string(), toml::Value::from("2.6.5"));
+
+        write_toml(&path, &value)?;
+
+        assert_eq!(fs::read_to_string(&path)?, "ruby = \"2.6.5\"\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_failed_writes() -> Result<(), Error> {
+        let tmpdir = TempDir::new("atomic")?;
+        let path = tmpdir.path().join("missing").join("plan.toml");
+
+        let err = write(&path, "contents").unwrap_err();
+
+        assert_eq!(err.path(), Some(path.as_path()));
+        assert_eq!(err.operation(), Some(Operation::Write));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_same_file_from_many_threads() -> Result<(), Error> {
+        let tmpdir = TempDir::new("atomic")?;
+        let path = tmpdir.path().join("launch.toml");
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        write(&path, format!("thread {}", i))?;
+                    }
+                    Ok::<_, crate::error::Error>(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+
+        assert!(fs::read_to_string(&path)?.starts_with("thread "));
+        assert_eq!(fs::read_dir(tmpdir.path())?.count(), 1);
+
+        Ok(())
+    }
+}
@@ -1,22 +1,27 @@
 use crate::{
+    atomic,
     build_plan::BuildPlan,
     buildpack::Buildpack,
+    config::Config,
+    digest,
+    env::Env,
     error::{Error, ErrorKind, Result},
-    layers::Layers,
+    layers::{Layer, Layers},
+    lifecycle::{self, PlanConvention, BUILDPACK_FILE},
+    output::Output,
     platform::Platform,
+    project::Project,
     stack::Stack,
 };
 use log::debug;
 use std::{
-    fs,
+    ffi::OsString,
     io::{self, Read},
     path::{Path, PathBuf},
 };
 
 const SUCCESS_STATUS_CODE: i32 = 0;
 
-const BUILDPACK_FILE: &str = "buildpack.toml";
-
 #[derive(Debug)]
 pub struct Build {
     pub root: PathBuf,
@@ -26,6 +31,11 @@ pub struct Build {
     pub layers: Layers,
     pub platform: Platform,
     pub stack: Stack,
+    /// The app's project.toml. Its `[[build.env]]` vars are merged into `platform.env`.
+    pub project: Option<Project>,
+    /// The `[[metadata.configurations]]` of buildpack.toml, resolved from `platform.env`.
+    pub config: Config,
+    pub output: Output,
 }
 
 impl Build {
@@ -36,61 +46,289 @@ impl Build {
         // need to use Box, so it can be `Sized` at compile time
         plan_reader: Option<Box<dyn Read>>,
     ) -> Result<Self> {
-        let buildpack = Buildpack::from_file(Self::find_toml()?)?;
-        let mut stdin_buf = String::new();
-        let mut reader = plan_reader.unwrap_or(Box::new(io::stdin()));
-        reader.read_to_string(&mut stdin_buf)?;
-        let build_plan: BuildPlan = toml::from_str(&stdin_buf)?;
-
-        Ok(Self {
-            root: std::env::current_dir()?,
-            build_plan: build_plan,
-            build_plan_output: plan.into(),
-            buildpack: buildpack,
-            layers: Layers::new(layers.as_ref()),
-            platform: Platform::new(platform.as_ref())?,
-            stack: Stack::new()?,
-        })
+        Self::with_inputs(Self::from_env()?, layers, platform, plan, plan_reader).build()
     }
 
-    pub fn success(&self, build_plan: &BuildPlan) -> Result<i32> {
-        let toml_string = toml::to_string(build_plan)?;
+    // the inputs given to `new`, on top of the ones read from the process
+    fn with_inputs<L: AsRef<Path>, P: AsRef<Path>, A: Into<PathBuf>>(
+        builder: BuildBuilder,
+        layers: L,
+        platform: P,
+        plan: A,
+        plan_reader: Option<Box<dyn Read>>,
+    ) -> BuildBuilder {
+        let mut builder = builder
+            .layers_dir(layers.as_ref())
+            .platform_dir(platform.as_ref())
+            .build_plan_output(plan);
+        if let Some(reader) = plan_reader {
+            // an explicit reader replaces the plan file the lifecycle arguments point at
+            builder.inputs.plan_file = None;
+            builder = builder.plan_reader(reader);
+        }
+
+        builder
+    }
 
-        fs::write(&self.build_plan_output, &toml_string)?;
+    /// A builder where every input has to be given explicitly, nothing is read from the process.
+    pub fn builder() -> BuildBuilder {
+        BuildBuilder::default()
+    }
+
+    /// A builder set up the way the lifecycle runs `bin/build`, reading the process env and
+    /// arguments, see `Build::from_args`. The app dir is the current dir.
+    pub fn from_env() -> Result<BuildBuilder> {
+        Ok(Self::from_args(Env::from_process(), std::env::args_os())?
+            .app_dir(std::env::current_dir()?))
+    }
+
+    /// Parses the `bin/build <layers> <platform> <plan>` arguments, `args[0]` being the
+    /// executable. `CNB_BUILDPACK_DIR`, `CNB_LAYERS_DIR`, `CNB_PLATFORM_DIR` and
+    /// `CNB_BP_PLAN_PATH` in `env` take precedence over the arguments. The stack is read from
+    /// `env`.
+    ///
+    /// The buildpack API version in buildpack.toml selects the plan convention: the original API
+    /// reads the build plan from stdin and writes it to the plan path, newer APIs read the
+    /// buildpack plan from the plan path.
+    pub fn from_args<I: IntoIterator<Item = OsString>>(env: Env, args: I) -> Result<BuildBuilder> {
+        let args: Vec<OsString> = args.into_iter().collect();
+        let buildpack_dir = Self::buildpack_dir(&env, &args)?;
+        let buildpack = Buildpack::from_file(buildpack_dir.join(BUILDPACK_FILE))?;
+        let plan_path = lifecycle::path(&env, lifecycle::BP_PLAN_PATH_ENV_VAR, &args, 3);
+
+        let mut builder = Self::builder();
+        builder.layers_dir = lifecycle::path(&env, lifecycle::LAYERS_DIR_ENV_VAR, &args, 1);
+        builder.inputs.platform_dir =
+            lifecycle::path(&env, lifecycle::PLATFORM_DIR_ENV_VAR, &args, 2);
+
+        builder = match (
+            PlanConvention::from_api(buildpack.api.as_deref()),
+            &plan_path,
+        ) {
+            (PlanConvention::File, Some(path)) => builder.plan_file(path),
+            (PlanConvention::File, None) => builder,
+            (PlanConvention::Stdin, _) => builder.plan_reader(io::stdin()),
+        };
+        builder.inputs.build_plan_output = plan_path;
+
+        Ok(builder.buildpack(buildpack).env(env))
+    }
+
+    pub fn success(&self, build_plan: &BuildPlan) -> Result<i32> {
+        atomic::write_toml(&self.build_plan_output, build_plan)?;
 
         debug!("Build success. Exiting with {}", SUCCESS_STATUS_CODE);
 
         Ok(SUCCESS_STATUS_CODE)
     }
 
+    /// Opt-in check that the current stack and its mixins are supported by buildpack.toml. If
+    /// they aren't, the reason is written to the output before the error is returned.
+    pub fn check_stack(&mut self) -> Result<()> {
+        if let Err(err) = self.stack.validate(&self.buildpack.stacks) {
+            self.output.warning(
+                &format!("{} does not support this stack", self.buildpack.info.id),
+                &err.to_string(),
+            );
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// The digest of the app files matching `patterns`, globs relative to the app dir such as
+    /// `Gemfile.lock` or `package-lock.json`, see `digest::files`.
+    pub fn fingerprint(&self, patterns: &[&str]) -> Result<String> {
+        digest::files(&self.root, patterns)
+    }
+
+    /// Whether the fingerprint of `patterns` differs from the one stored under `key` by
+    /// `store_fingerprint`, so read the cached metadata with `Layer::read_metadata` first. A
+    /// layer without one counts as changed. Nothing is written.
+    pub fn fingerprint_changed(&self, layer: &Layer, key: &str, patterns: &[&str]) -> Result<bool> {
+        let fingerprint = self.fingerprint(patterns)?;
+        let changed = layer
+            .config
+            .metadata
+            .get(key)
+            .and_then(|value| value.as_str())
+            != Some(fingerprint.as_str());
+        debug!(
+            "Fingerprint {} => {} (changed: {})",
+            key, fingerprint, changed
+        );
+
+        Ok(changed)
+    }
+
+    /// Stores the fingerprint of `patterns` under `key` in the layer's metadata and writes it.
+    /// Run it once the layer has been rebuilt, so that a failed rebuild isn't mistaken for an up
+    /// to date layer by the next build.
+    pub fn store_fingerprint(
+        &self,
+        layer: &mut Layer,
+        key: &str,
+        patterns: &[&str],
+    ) -> Result<String> {
+        let fingerprint = self.fingerprint(patterns)?;
+        layer.config.metadata.insert(key, fingerprint.as_str());
+        layer.write_metadata()?;
+
+        Ok(fingerprint)
+    }
+
     pub fn fail(&self, code: i32) -> i32 {
         debug!("Build failed. Exiting with {}", code);
 
         code
     }
 
+    /// Prints `err` to stderr and returns the exit code it maps to.
+    pub fn report(&self, err: &Error) -> i32 {
+        let code = err.report();
+        debug!("Build produced an error. Exiting with {}", code);
+
+        code
+    }
+
+    #[cfg(test)]
     fn find_toml() -> Result<PathBuf> {
-        Ok(Self::buildpack_dir()?.join(BUILDPACK_FILE))
+        let args: Vec<OsString> = std::env::args_os().collect();
+
+        Ok(Self::buildpack_dir(&Env::from_process(), &args)?.join(BUILDPACK_FILE))
     }
 
-    fn buildpack_dir() -> Result<PathBuf> {
-        if let Some(bin_path) = std::env::args_os().nth(0) {
-            let mut buildpack_dir = PathBuf::from(bin_path.into_string()?);
-            buildpack_dir.pop();
-            buildpack_dir.pop();
-            Ok(buildpack_dir)
-        } else {
-            Err(Error::from(ErrorKind::NoArgs))
-        }
+    fn buildpack_dir(env: &Env, args: &[OsString]) -> Result<PathBuf> {
+        lifecycle::buildpack_dir(env, args).ok_or_else(|| Error::from(ErrorKind::NoArgs))
+    }
+}
+
+/// Constructs a `Build`, see `Build::builder` and `Build::from_env`.
+///
+/// The stack and platform are read using the builder's env unless they're given directly.
+#[derive(Default)]
+pub struct BuildBuilder {
+    inputs: lifecycle::Inputs,
+    buildpack: Option<Buildpack>,
+    buildpack_dir: Option<PathBuf>,
+    layers_dir: Option<PathBuf>,
+}
+
+impl BuildBuilder {
+    /// The lifecycle's env, used to read the stack and locate the service bindings.
+    pub fn env(mut self, env: Env) -> Self {
+        self.inputs.env = env;
+        self
+    }
+
+    pub fn app_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.inputs.app_dir = Some(dir.into());
+        self
+    }
+
+    /// Takes precedence over `buildpack_dir`.
+    pub fn buildpack(mut self, buildpack: Buildpack) -> Self {
+        self.buildpack = Some(buildpack);
+        self
+    }
+
+    /// The directory containing buildpack.toml.
+    pub fn buildpack_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.buildpack_dir = Some(dir.into());
+        self
+    }
+
+    pub fn layers_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.layers_dir = Some(dir.into());
+        self
+    }
+
+    pub fn stack(mut self, stack: Stack) -> Self {
+        self.inputs.stack = Some(stack);
+        self
+    }
+
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.inputs.platform = Some(platform);
+        self
+    }
+
+    pub fn platform_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.inputs.platform_dir = Some(dir.into());
+        self
+    }
+
+    /// Takes precedence over `plan_reader`.
+    pub fn build_plan(mut self, build_plan: BuildPlan) -> Self {
+        self.inputs.build_plan = Some(build_plan);
+        self
+    }
+
+    /// Reads the build plan TOML from the file at `path`. Takes precedence over `plan_reader`.
+    pub fn plan_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.inputs.plan_file = Some(path.into());
+        self
+    }
+
+    /// Reads the build plan TOML from `reader`. Without a plan or a reader the plan is empty.
+    pub fn plan_reader<R: Read + 'static>(mut self, reader: R) -> Self {
+        self.inputs.plan_reader = Some(Box::new(reader));
+        self
+    }
+
+    pub fn build_plan_output<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.inputs.build_plan_output = Some(path.into());
+        self
+    }
+
+    /// Defaults to the app's project.toml, if it has one.
+    pub fn project(mut self, project: Project) -> Self {
+        self.inputs.project = Some(project);
+        self
+    }
+
+    /// Defaults to `Output::new()`.
+    pub fn output(mut self, output: Output) -> Self {
+        self.inputs.output = Some(output);
+        self
+    }
+
+    pub fn build(self) -> Result<Build> {
+        let buildpack = match (self.buildpack, self.buildpack_dir) {
+            (Some(buildpack), _) => buildpack,
+            (None, Some(dir)) => Buildpack::from_file(dir.join(BUILDPACK_FILE))?,
+            (None, None) => return Err(Error::from(ErrorKind::MissingInput("buildpack dir"))),
+        };
+        let context = self.inputs.resolve()?;
+        let layers_dir = self
+            .layers_dir
+            .ok_or(ErrorKind::MissingInput("layers dir"))?;
+
+        let config = Config::resolve(&buildpack, &context.platform.env)?;
+        let mut output = context.output;
+        config.print(&mut output);
+
+        Ok(Build {
+            root: context.root,
+            build_plan: context.build_plan,
+            build_plan_output: context.build_plan_output,
+            buildpack,
+            layers: Layers::new(layers_dir),
+            platform: context.platform,
+            stack: context.stack,
+            project: context.project,
+            config,
+            output,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::build_plan::Dependency;
+    use crate::{build_plan::Dependency, error::Operation, testing::Harness};
     use failure::Error;
-    use std::result::Result;
+    use std::{fs, result::Result};
     use tempdir::TempDir;
 
     struct Setup {
@@ -130,11 +368,11 @@ run-images = ["heroku/heroku-18"]
         std::env::set_var("CNB_STACK_ID", "aspen");
 
         Ok(Setup {
-            buildpack_toml_path: buildpack_toml_path,
-            layers_dir: layers_dir,
-            platform_dir: platform_dir,
+            buildpack_toml_path,
+            layers_dir,
+            platform_dir,
             build_plan_path: build_plan,
-            old_env_var: old_env_var,
+            old_env_var,
             _tmpdir: temp_dir,
         })
     }
@@ -188,7 +426,7 @@ version = "2.6.5"
             &setup.layers_dir,
             &setup.platform_dir,
             &setup.build_plan_path,
-            Some(Box::new(&stdin[..])),
+            Some(Box::new(stdin)),
         );
 
         reset_cnb_stack_id(setup.old_env_var);
@@ -200,4 +438,144 @@ version = "2.6.5"
 
         Ok(())
     }
+
+    const BUILDPACK_TOML: &str = r#"[buildpack]
+id = "heroku/ruby"
+name = "Heroku Ruby"
+version = "1.0.0"
+"#;
+
+    // writes buildpack.toml for `api` into `dir`, returns the env the lifecycle runs build with
+    fn lifecycle_env(dir: &Path, api: Option<&str>) -> Result<Env, Error> {
+        fs::create_dir_all(dir)?;
+        let api = api
+            .map(|api| format!("api = \"{}\"\n\n", api))
+            .unwrap_or_default();
+        fs::write(dir.join(BUILDPACK_FILE), api + BUILDPACK_TOML)?;
+        let mut env = Env::new();
+        env.set_var("CNB_STACK_ID", "heroku-20");
+        env.set_var("CNB_BUILDPACK_DIR", dir);
+
+        Ok(env)
+    }
+
+    #[test]
+    fn it_builds_from_injected_inputs() -> Result<(), Error> {
+        let tmpdir = TempDir::new("build")?;
+        let app_dir = tmpdir.path().join("app");
+        let buildpack_dir = tmpdir.path().join("buildpack");
+        fs::create_dir_all(&app_dir)?;
+        let env = lifecycle_env(&buildpack_dir, None)?;
+
+        let build = Build::builder()
+            .env(env)
+            .app_dir(&app_dir)
+            .buildpack_dir(&buildpack_dir)
+            .layers_dir(tmpdir.path().join("layers"))
+            .platform_dir(tmpdir.path().join("platform"))
+            .build_plan_output(tmpdir.path().join("plan.toml"))
+            .build()?;
+
+        assert_eq!(build.root, app_dir);
+        assert_eq!(build.buildpack.info.id, "heroku/ruby");
+        assert_eq!(*build.stack, "heroku-20");
+        assert!(build.build_plan.is_empty());
+
+        let missing = Build::builder().buildpack_dir(&buildpack_dir).build();
+        assert!(missing.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_plan_file_for_newer_apis() -> Result<(), Error> {
+        let tmpdir = TempDir::new("build")?;
+        let buildpack_dir = tmpdir.path().join("buildpack");
+        let plan_path = tmpdir.path().join("plan.toml");
+        let env = lifecycle_env(&buildpack_dir, Some("0.2"))?;
+        fs::write(&plan_path, "[ruby]\nversion = \"2.6.5\"\n")?;
+        let args = vec![
+            buildpack_dir.join("bin").join("build").into_os_string(),
+            tmpdir.path().join("layers").into_os_string(),
+            tmpdir.path().join("platform").into_os_string(),
+            plan_path.clone().into_os_string(),
+        ];
+
+        let build = Build::from_args(env, args)?
+            .app_dir(tmpdir.path())
+            .build()?;
+
+        assert_eq!(build.buildpack.api.as_deref(), Some("0.2"));
+        assert_eq!(build.build_plan.get("ruby").unwrap().version, "2.6.5");
+        assert_eq!(build.build_plan_output, plan_path);
+
+        fs::write(&plan_path, "[ruby")?;
+        let err = Build::builder()
+            .buildpack(build.buildpack)
+            .plan_file(&plan_path)
+            .build()
+            .unwrap_err();
+        assert_eq!(err.operation(), Some(Operation::Parse));
+        assert_eq!(err.path(), Some(plan_path.as_path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prefers_an_explicit_plan_reader_over_the_plan_file() -> Result<(), Error> {
+        let tmpdir = TempDir::new("build")?;
+        let plan_path = tmpdir.path().join("plan.toml");
+        let mut env = lifecycle_env(&tmpdir.path().join("buildpack"), Some("0.2"))?;
+        env.set_var("CNB_BP_PLAN_PATH", &plan_path);
+        fs::write(&plan_path, "[ruby]\nversion = \"2.6.5\"\n")?;
+        let builder = Build::from_args(env, Vec::new())?.app_dir(tmpdir.path());
+
+        let build = Build::with_inputs(
+            builder,
+            tmpdir.path().join("layers"),
+            tmpdir.path().join("platform"),
+            tmpdir.path().join("output.toml"),
+            Some(Box::new(&b"[ruby]\nversion = \"2.7.0\""[..])),
+        )
+        .build()?;
+
+        assert_eq!(build.build_plan.get("ruby").unwrap().version, "2.7.0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fingerprints_app_files_into_layer_metadata() -> Result<(), Error> {
+        let harness = Harness::new()?;
+        harness.buildpack_toml(BUILDPACK_TOML)?;
+        harness.app_file("Gemfile.lock", "GEM")?;
+
+        harness.build(|build| {
+            let patterns = ["Gemfile.lock"];
+            let mut layer = build.layers.add("gems")?;
+            assert!(build.fingerprint_changed(&layer, "gemfile_lock", &patterns)?);
+            assert!(!layer.config_path().exists());
+
+            let stored = build.store_fingerprint(&mut layer, "gemfile_lock", &patterns)?;
+
+            let mut cached = build.layers.add("gems")?;
+            cached.read_metadata()?;
+            assert_eq!(
+                cached
+                    .config
+                    .metadata
+                    .get("gemfile_lock")
+                    .and_then(|value| value.as_str()),
+                Some(stored.as_str())
+            );
+            assert!(!build.fingerprint_changed(&cached, "gemfile_lock", &patterns)?);
+
+            fs::write(build.root.join("Gemfile.lock"), "GEM\n  remote")?;
+            assert!(build.fingerprint_changed(&cached, "gemfile_lock", &patterns)?);
+
+            Ok(0)
+        })?;
+
+        Ok(())
+    }
 }
@@ -13,9 +13,15 @@ use std::{
 #[derive(Debug)]
 pub struct BuildPlan(HashMap<String, Dependency>);
 
+impl Default for BuildPlan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BuildPlan {
     pub fn new() -> Self {
-        Self { 0: HashMap::new() }
+        Self(HashMap::new())
     }
 
     pub fn insert<S: Into<String>>(&mut self, key: S, value: Dependency) {
@@ -161,7 +167,7 @@ version = "alpha"
         let toml_string = r#"
 version = "alpha"
 "#;
-        let dependency_result: Result<Dependency, toml::de::Error> = toml::from_str(&toml_string);
+        let dependency_result: Result<Dependency, toml::de::Error> = toml::from_str(toml_string);
 
         assert!(dependency_result.is_ok());
         if let Ok(dependency) = dependency_result {
@@ -178,7 +184,7 @@ version = "alpha"
 version = "alpha"
     "#;
 
-        let build_plan_result: Result<BuildPlan, toml::de::Error> = toml::from_str(&toml_string);
+        let build_plan_result: Result<BuildPlan, toml::de::Error> = toml::from_str(toml_string);
 
         assert!(build_plan_result.is_ok());
         if let Ok(build_plan) = build_plan_result {
@@ -199,7 +205,7 @@ version = "alpha"
 foo = "bar"
 "#;
 
-        let build_plan_result: Result<BuildPlan, toml::de::Error> = toml::from_str(&toml_string);
+        let build_plan_result: Result<BuildPlan, toml::de::Error> = toml::from_str(toml_string);
 
         assert!(build_plan_result.is_ok());
         if let Ok(build_plan) = build_plan_result {
@@ -1,5 +1,5 @@
 use crate::{
-    error::{ErrorKind, Result},
+    error::{Operation, Result, ResultExt},
     metadata::Metadata,
 };
 use log::debug;
@@ -8,6 +8,10 @@ use std::{fs, path::Path};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Buildpack {
+    /// The buildpack API version, buildpacks without one use the original API.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
     #[serde(rename = "buildpack")]
     pub info: Info,
     #[serde(default)]
@@ -25,6 +29,7 @@ impl Buildpack {
         version: V,
     ) -> Self {
         let buildpack = Self {
+            api: None,
             info: Info::new(id, name, version),
             stacks: Vec::new(),
             metadata: Metadata::new(),
@@ -37,9 +42,9 @@ impl Buildpack {
 
     pub fn from_file<P: AsRef<Path>>(file: P) -> Result<Self> {
         let file_path = file.as_ref();
-        let toml_string = fs::read_to_string(file_path)
-            .map_err(|_| ErrorKind::FileNotFound(file_path.to_path_buf()))?;
-        let buildpack: Buildpack = toml::from_str(&toml_string)?;
+        let toml_string = fs::read_to_string(file_path).with_context(Operation::Read, file_path)?;
+        let buildpack: Buildpack =
+            toml::from_str(&toml_string).with_context(Operation::Parse, file_path)?;
 
         debug!("Buildpack: {:#?}", buildpack);
 
@@ -120,7 +125,7 @@ mod tests {
     #[test]
     fn stack_deserializes_from_toml() -> Result<(), Error> {
         let toml_string = r#"id = "aspen""#;
-        let stack: Stack = toml::from_str(&toml_string)?;
+        let stack: Stack = toml::from_str(toml_string)?;
 
         assert_eq!(stack.id, "aspen");
 
@@ -192,7 +197,7 @@ id = 'heroku/ruby'
 name = 'Heroku Ruby'
 version = '1.0.0'
 "#;
-        let buildpack: Buildpack = toml::from_str(&toml_string)?;
+        let buildpack: Buildpack = toml::from_str(toml_string)?;
 
         assert_eq!("heroku/ruby", buildpack.info.id);
 
@@ -208,4 +213,17 @@ version = '1.0.0'
 
         Ok(())
     }
+
+    #[test]
+    fn it_reports_the_failed_read() -> Result<(), Error> {
+        let tmpdir = TempDir::new("buildpack")?;
+        let path = tmpdir.path().join("buildpack.toml");
+
+        let err = Buildpack::from_file(&path).unwrap_err();
+
+        assert_eq!(err.operation(), Some(Operation::Read));
+        assert_eq!(err.path(), Some(path.as_path()));
+
+        Ok(())
+    }
 }
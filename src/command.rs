@@ -0,0 +1,337 @@
+//! Runs build steps (`bundle install`, `npm ci`) as subprocesses.
+
+use crate::{
+    env::Env,
+    error::{Error, ErrorKind, Operation, Result, ResultExt},
+    layers::{shell_escape, EnvSet},
+    output::Output,
+};
+use log::debug;
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{self, ExitStatus, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+// lines of output kept in the error of a failed command
+const FAILURE_OUTPUT_LINES: usize = 20;
+
+/// A subprocess with an explicit environment.
+///
+/// The env starts as the process env unless `env` is given, and layer env modifications are
+/// applied on top of it in order.
+#[derive(Debug)]
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Option<Env>,
+    current_dir: Option<PathBuf>,
+}
+
+impl Command {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            env: None,
+            current_dir: None,
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Replaces the whole environment of the command, e.g. with `Platform::env`.
+    pub fn env(&mut self, env: Env) -> &mut Self {
+        self.env = Some(env);
+        self
+    }
+
+    pub fn env_var<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.env_mut().set_var(key, value);
+        self
+    }
+
+    /// Applies a layer's env modifications, e.g. `layer.envs.build`.
+    pub fn layer_env(&mut self, env_set: &EnvSet) -> &mut Self {
+        env_set.apply(self.env_mut());
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Runs the command, streaming its stdout and stderr indented as steps of `output`.
+    pub fn run(&self, output: &mut Output) -> Result<CommandOutput> {
+        self.execute(Some(output))
+    }
+
+    /// Runs the command without printing its output.
+    pub fn capture(&self) -> Result<CommandOutput> {
+        self.execute(None)
+    }
+
+    fn env_mut(&mut self) -> &mut Env {
+        self.env.get_or_insert_with(Env::from_process)
+    }
+
+    fn execute(&self, mut output: Option<&mut Output>) -> Result<CommandOutput> {
+        let command_line = self.to_string();
+        debug!("Running: {}", command_line);
+
+        let mut command = process::Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(env) = &self.env {
+            env.apply_to(&mut command);
+        }
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+
+        let mut child = command
+            .spawn()
+            .with_context(Operation::Execute, &self.program)?;
+
+        // both pipes are read on their own thread, so neither fills up and blocks the child
+        let (sender, receiver) = mpsc::channel();
+        let readers = vec![
+            child
+                .stdout
+                .take()
+                .map(|stdout| read_lines(stdout, Stream::Stdout, sender.clone())),
+            child
+                .stderr
+                .take()
+                .map(|stderr| read_lines(stderr, Stream::Stderr, sender)),
+        ];
+
+        let mut captured = CommandOutput::default();
+        for (stream, line) in receiver {
+            if let Some(output) = output.as_mut() {
+                output.step(&line);
+            }
+            captured.push(stream, &line);
+        }
+        for reader in readers.into_iter().flatten() {
+            let _ = reader.join();
+        }
+
+        let status = child
+            .wait()
+            .with_context(Operation::Execute, &self.program)?;
+        captured.status = Some(status);
+        debug!("{} exited with {}", command_line, status);
+
+        if status.success() {
+            Ok(captured)
+        } else {
+            Err(Error::from(ErrorKind::CommandFailed(Box::new(
+                CommandFailure {
+                    command: command_line,
+                    status,
+                    output: captured.tail(FAILURE_OUTPUT_LINES),
+                },
+            ))))
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    /// The command line, quoted so it can be pasted into a shell.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = std::iter::once(&self.program).chain(&self.args);
+        for (index, word) in words.enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+
+            let word = word.to_string_lossy();
+            if !word.is_empty()
+                && word
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c))
+            {
+                f.write_str(&word)?;
+            } else {
+                f.write_str(&shell_escape(&word))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+fn read_lines<R: Read + Send + 'static>(
+    reader: R,
+    stream: Stream,
+    sender: mpsc::Sender<(Stream, String)>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(reader)
+            .split(b'\n')
+            .map_while(io::Result::ok)
+        {
+            let line = String::from_utf8_lossy(&line)
+                .trim_end_matches('\r')
+                .to_string();
+            if sender.send((stream, line)).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// The output of a command that ran.
+#[derive(Debug, Default, Clone)]
+pub struct CommandOutput {
+    pub status: Option<ExitStatus>,
+    pub stdout: String,
+    pub stderr: String,
+    // stdout and stderr interleaved in the order they were read
+    combined: Vec<String>,
+}
+
+impl CommandOutput {
+    fn push(&mut self, stream: Stream, line: &str) {
+        let captured = match stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        };
+        captured.push_str(line);
+        captured.push('\n');
+        self.combined.push(line.to_string());
+    }
+
+    fn tail(&self, lines: usize) -> String {
+        let start = self.combined.len().saturating_sub(lines);
+
+        self.combined[start..].join("\n")
+    }
+}
+
+/// A command that exited with a non-zero status.
+#[derive(Debug)]
+pub struct CommandFailure {
+    pub command: String,
+    pub status: ExitStatus,
+    /// The last lines of its stdout and stderr.
+    pub output: String,
+}
+
+impl fmt::Display for CommandFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Command `{}` failed with {}", self.command, self.status)?;
+        if !self.output.is_empty() {
+            write!(f, "\n\n{}", self.output)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::SharedBuffer;
+    use failure::Error;
+    use std::result::Result;
+
+    #[test]
+    fn it_streams_and_captures_output() -> Result<(), Error> {
+        let buffer = SharedBuffer::default();
+        let mut output = Output::from_writer(buffer.clone(), false);
+
+        let result = Command::new("sh")
+            .args(["-c", "echo installing; echo; echo done; echo warning >&2"])
+            .run(&mut output)?;
+
+        assert_eq!(result.stdout, "installing\n\ndone\n");
+        assert_eq!(result.stderr, "warning\n");
+        assert!(result.status.unwrap().success());
+        let printed = buffer.contents();
+        // stdout and stderr lines can interleave in any order
+        let (warnings, lines): (Vec<&str>, Vec<&str>) =
+            printed.lines().partition(|line| *line == "       warning");
+        assert_eq!(lines, vec!["       installing", "       ", "       done"]);
+        assert_eq!(warnings.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_sets_the_layered_env() -> Result<(), Error> {
+        let mut env = Env::new();
+        env.set_var("PATH", "/usr/bin:/bin");
+        env.set_var("RAILS_ENV", "development");
+        let mut env_set = EnvSet::new();
+        env_set.r#override.set_var("RAILS_ENV", "production");
+        env_set.append_path.set_var("PATH", "/layers/ruby/bin");
+
+        let result = Command::new("sh")
+            .args(["-c", "echo $RAILS_ENV $PATH $HOME"])
+            .env(env)
+            .layer_env(&env_set)
+            .capture()?;
+
+        assert_eq!(result.stdout, "production /layers/ruby/bin:/usr/bin:/bin\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_with_the_command_and_status() {
+        let err = Command::new("sh")
+            .args(["-c", "echo 'Could not find gem rails'; exit 3"])
+            .capture()
+            .unwrap_err();
+
+        match err.kind() {
+            ErrorKind::CommandFailed(failure) => {
+                assert_eq!(
+                    failure.command,
+                    "sh -c 'echo '\\''Could not find gem rails'\\''; exit 3'"
+                );
+                assert_eq!(failure.status.code(), Some(3));
+                assert_eq!(failure.output, "Could not find gem rails");
+            }
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn it_reports_missing_programs() {
+        let err = Command::new("does-not-exist").capture().unwrap_err();
+
+        assert_eq!(err.operation(), Some(Operation::Execute));
+        assert_eq!(err.path(), Some(Path::new("does-not-exist")));
+    }
+}
@@ -0,0 +1,311 @@
+//! Buildpack configuration through `BP_*` env vars, declared in buildpack.toml:
+//!
+//! ```toml
+//! [[metadata.configurations]]
+//! name = "BP_RUBY_VERSION"
+//! default = "2.6.5"
+//! description = "The Ruby version to install"
+//! build = true
+//! ```
+
+use crate::{
+    buildpack::Buildpack,
+    env::Env,
+    error::{ErrorKind, Result},
+    output::Output,
+};
+use log::debug;
+use serde_derive::Deserialize;
+use std::fmt;
+use toml::value::Value;
+
+const CONFIGURATIONS_KEY: &str = "configurations";
+
+/// An option declared in `[[metadata.configurations]]`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Configuration {
+    pub name: String,
+    #[serde(default)]
+    pub r#type: ConfigType,
+    pub default: Option<Value>,
+    pub description: Option<String>,
+    /// Used during the build.
+    #[serde(default)]
+    pub build: bool,
+    /// Used by the app at runtime.
+    #[serde(default)]
+    pub launch: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigType {
+    #[default]
+    String,
+    Bool,
+    Int,
+}
+
+impl fmt::Display for ConfigType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ConfigType::String => "string",
+            ConfigType::Bool => "bool",
+            ConfigType::Int => "int",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    String(String),
+    Bool(bool),
+    Int(i64),
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigValue::String(ref value) => f.write_str(value),
+            ConfigValue::Bool(value) => value.fmt(f),
+            ConfigValue::Int(value) => value.fmt(f),
+        }
+    }
+}
+
+/// Where a resolved value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Env,
+    Default,
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting {
+    pub configuration: Configuration,
+    /// `None` if the env var isn't set and there's no default.
+    pub value: Option<ConfigValue>,
+    pub source: Option<Source>,
+}
+
+/// The buildpack's configurations, resolved against the platform env.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    settings: Vec<Setting>,
+}
+
+impl Config {
+    /// Resolves the configurations declared by `buildpack` from `env`, falling back to their
+    /// defaults. It's an error if a value doesn't match the declared type.
+    pub fn resolve(buildpack: &Buildpack, env: &Env) -> Result<Self> {
+        let configurations: Vec<Configuration> = match buildpack.metadata.get(CONFIGURATIONS_KEY) {
+            Some(value) => value.clone().try_into()?,
+            None => Vec::new(),
+        };
+
+        let mut settings = Vec::new();
+        for configuration in configurations {
+            let (value, source) = match env.var_os(&configuration.name) {
+                Some(raw) => {
+                    let raw = raw.to_string_lossy();
+                    (Some(parse(&configuration, &raw)?), Some(Source::Env))
+                }
+                None => match &configuration.default {
+                    Some(default) => (
+                        Some(parse_default(&configuration, default)?),
+                        Some(Source::Default),
+                    ),
+                    None => (None, None),
+                },
+            };
+
+            settings.push(Setting {
+                configuration,
+                value,
+                source,
+            });
+        }
+
+        let config = Self { settings };
+        debug!("Configuration: {:#?}", config);
+
+        Ok(config)
+    }
+
+    pub fn settings(&self) -> &[Setting] {
+        &self.settings
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ConfigValue> {
+        self.settings
+            .iter()
+            .find(|setting| setting.configuration.name == name)
+            .and_then(|setting| setting.value.as_ref())
+    }
+
+    /// The value of a `string` configuration.
+    pub fn string(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(ConfigValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value of a `bool` configuration.
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.get(name) {
+            Some(ConfigValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value of an `int` configuration.
+    pub fn int(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(ConfigValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Prints the effective configuration, one line per setting.
+    pub fn print(&self, output: &mut Output) {
+        if self.settings.is_empty() {
+            return;
+        }
+
+        output.header("Configuration");
+        for setting in &self.settings {
+            let value = match (&setting.value, setting.source) {
+                (Some(value), Some(Source::Default)) => format!("{} (default)", value),
+                (Some(value), _) => value.to_string(),
+                (None, _) => "(not set)".to_string(),
+            };
+            let mut line = format!("{}={}", setting.configuration.name, value);
+            if setting.configuration.launch {
+                line.push_str(" [launch]");
+            }
+            if let Some(description) = &setting.configuration.description {
+                line = format!("{}  # {}", line, description);
+            }
+
+            output.step(&line);
+        }
+    }
+}
+
+fn parse(configuration: &Configuration, raw: &str) -> Result<ConfigValue> {
+    let invalid = || {
+        ErrorKind::InvalidConfig(
+            configuration.name.clone(),
+            format!("{:?} is not a valid {}", raw, configuration.r#type),
+        )
+    };
+
+    Ok(match configuration.r#type {
+        ConfigType::String => ConfigValue::String(raw.to_string()),
+        ConfigType::Bool => match raw.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" => ConfigValue::Bool(true),
+            "false" | "0" | "no" | "" => ConfigValue::Bool(false),
+            _ => return Err(invalid().into()),
+        },
+        ConfigType::Int => ConfigValue::Int(raw.trim().parse().map_err(|_| invalid())?),
+    })
+}
+
+fn parse_default(configuration: &Configuration, default: &Value) -> Result<ConfigValue> {
+    match (configuration.r#type, default) {
+        (ConfigType::Bool, Value::Boolean(value)) => Ok(ConfigValue::Bool(*value)),
+        (ConfigType::Int, Value::Integer(value)) => Ok(ConfigValue::Int(*value)),
+        (_, Value::String(value)) => parse(configuration, value),
+        (_, value) => parse(configuration, &value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::SharedBuffer;
+    use failure::Error;
+    use std::result::Result;
+
+    const BUILDPACK_TOML: &str = r#"
+[buildpack]
+id = "heroku/ruby"
+name = "Heroku Ruby"
+version = "1.0.0"
+
+[[metadata.configurations]]
+name = "BP_RUBY_VERSION"
+default = "2.6.5"
+description = "The Ruby version"
+build = true
+
+[[metadata.configurations]]
+name = "BP_KEEP_FILES"
+type = "bool"
+default = false
+build = true
+
+[[metadata.configurations]]
+name = "BP_WEB_CONCURRENCY"
+type = "int"
+launch = true
+"#;
+
+    #[test]
+    fn it_resolves_from_env_and_defaults() -> Result<(), Error> {
+        let buildpack: Buildpack = toml::from_str(BUILDPACK_TOML)?;
+        let mut env = Env::new();
+        env.set_var("BP_KEEP_FILES", "TRUE");
+
+        let config = Config::resolve(&buildpack, &env)?;
+
+        assert_eq!(config.string("BP_RUBY_VERSION"), Some("2.6.5"));
+        assert_eq!(config.bool("BP_KEEP_FILES"), Some(true));
+        assert_eq!(config.int("BP_WEB_CONCURRENCY"), None);
+        assert_eq!(config.settings()[0].source, Some(Source::Default));
+        assert_eq!(config.settings()[1].source, Some(Source::Env));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_validates_types() -> Result<(), Error> {
+        let buildpack: Buildpack = toml::from_str(BUILDPACK_TOML)?;
+        let mut env = Env::new();
+        env.set_var("BP_WEB_CONCURRENCY", "lots");
+
+        let err = Config::resolve(&buildpack, &env).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid configuration BP_WEB_CONCURRENCY: "lots" is not a valid int"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_prints_the_configuration() -> Result<(), Error> {
+        let buildpack: Buildpack = toml::from_str(BUILDPACK_TOML)?;
+        let mut env = Env::new();
+        env.set_var("BP_WEB_CONCURRENCY", "4");
+        let buffer = SharedBuffer::default();
+        let mut output = Output::from_writer(buffer.clone(), false);
+
+        Config::resolve(&buildpack, &env)?.print(&mut output);
+
+        assert_eq!(
+            buffer.contents(),
+            "-----> Configuration
+       BP_RUBY_VERSION=2.6.5 (default)  # The Ruby version
+       BP_KEEP_FILES=false (default)
+       BP_WEB_CONCURRENCY=4 [launch]
+"
+        );
+
+        Ok(())
+    }
+}
@@ -1,9 +1,23 @@
-use crate::{build_plan::BuildPlan, error::Result, platform::Platform, stack::Stack};
+use crate::{
+    atomic,
+    build_plan::BuildPlan,
+    buildpack::Buildpack,
+    digest,
+    env::Env,
+    error::{Error, ErrorKind, Operation, Result, ResultExt},
+    lifecycle::{self, PlanConvention},
+    output::Output,
+    platform::Platform,
+    project::Project,
+    stack::Stack,
+};
 use log::debug;
+use serde::de::DeserializeOwned;
 use std::{
+    ffi::OsString,
     fs,
     io::{self, Read},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 const FAIL_STATUS_CODE: i32 = 100;
@@ -11,31 +25,152 @@ const PASS_STATUS_CODE: i32 = 0;
 
 #[derive(Debug)]
 pub struct Detect {
+    /// The app source being detected.
+    pub root: PathBuf,
     pub stack: Stack,
     pub platform: Platform,
     pub build_plan: BuildPlan,
     pub build_plan_output: PathBuf,
+    /// The app's project.toml. Its `[[build.env]]` vars are merged into `platform.env`.
+    pub project: Option<Project>,
+    pub output: Output,
 }
 
 impl Detect {
+    /// Reads the build plan from `plan_reader`. Without one, it's read the way `from_env` does,
+    /// which looks up the buildpack API version in buildpack.toml.
     pub fn new<P: Into<PathBuf>, L: Into<PathBuf>>(
         platform_dir: P,
         build_plan_output: L,
         // need to use Box, so it can be `Sized` at compile time
         plan_reader: Option<Box<dyn Read>>,
     ) -> Result<Self> {
-        let mut stdin_buf = String::new();
-        let mut reader = plan_reader.unwrap_or(Box::new(io::stdin()));
-        reader.read_to_string(&mut stdin_buf)?;
-        let build_plan: BuildPlan = toml::from_str(&stdin_buf)?;
-        Ok(Self {
-            stack: Stack::new()?,
-            build_plan: build_plan,
-            platform: Platform::new(platform_dir.into())?,
-            build_plan_output: build_plan_output.into(),
+        // with an explicit reader there's no need to find buildpack.toml to pick the convention
+        let builder = match plan_reader {
+            Some(reader) => Self::builder()
+                .env(Env::from_process())
+                .app_dir(std::env::current_dir()?)
+                .plan_reader(reader),
+            None => Self::from_env()?,
+        };
+
+        builder
+            .platform_dir(platform_dir)
+            .build_plan_output(build_plan_output)
+            .build()
+    }
+
+    /// A builder where every input has to be given explicitly, nothing is read from the process.
+    pub fn builder() -> DetectBuilder {
+        DetectBuilder::default()
+    }
+
+    /// A builder set up the way the lifecycle runs `bin/detect`, reading the process env and
+    /// arguments, see `Detect::from_args`. The app dir is the current dir.
+    pub fn from_env() -> Result<DetectBuilder> {
+        Ok(Self::from_args(Env::from_process(), std::env::args_os())?
+            .app_dir(std::env::current_dir()?))
+    }
+
+    /// Parses the `bin/detect <platform> <plan>` arguments, `args[0]` being the executable.
+    /// `CNB_PLATFORM_DIR` and `CNB_BUILD_PLAN_PATH` in `env` take precedence over the arguments.
+    /// The stack is read from `env`.
+    ///
+    /// The build plan is read from stdin for the original buildpack API, newer APIs don't pass
+    /// one. The API version is read from buildpack.toml in `CNB_BUILDPACK_DIR` or the parent of
+    /// the executable's dir, the original API is assumed without one.
+    pub fn from_args<I: IntoIterator<Item = OsString>>(env: Env, args: I) -> Result<DetectBuilder> {
+        let args: Vec<OsString> = args.into_iter().collect();
+        let buildpack_dir = lifecycle::buildpack_dir(&env, &args);
+
+        let mut builder = Self::builder();
+        if PlanConvention::from_buildpack_dir(buildpack_dir.as_deref())? == PlanConvention::Stdin {
+            builder = builder.plan_reader(io::stdin());
+        }
+        builder.inputs.platform_dir =
+            lifecycle::path(&env, lifecycle::PLATFORM_DIR_ENV_VAR, &args, 1);
+        builder.inputs.build_plan_output =
+            lifecycle::path(&env, lifecycle::BUILD_PLAN_PATH_ENV_VAR, &args, 2);
+
+        Ok(builder.env(env))
+    }
+
+    /// Opt-in check that the current stack and its mixins are supported by buildpack.toml. If
+    /// they aren't, the reason is written to the output and `report` maps the returned error to
+    /// the fail status code.
+    pub fn check_stack(&mut self, buildpack: &Buildpack) -> Result<()> {
+        if let Err(err) = self.stack.validate(&buildpack.stacks) {
+            self.output.warning(
+                &format!("{} does not support this stack", buildpack.info.id),
+                &err.to_string(),
+            );
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Whether `path`, relative to the app dir, exists.
+    pub fn has_file<P: AsRef<Path>>(&self, path: P) -> bool {
+        let exists = self.root.join(path.as_ref()).exists();
+        debug!(
+            "{} {}",
+            path.as_ref().display(),
+            if exists { "found" } else { "not found" }
+        );
+
+        exists
+    }
+
+    /// Whether any path in the app dir matches the glob `pattern`, e.g. `**/*.csproj`.
+    pub fn has_glob(&self, pattern: &str) -> Result<bool> {
+        match digest::glob_in(&self.root, pattern)?.flatten().next() {
+            Some(path) => {
+                debug!("{} matched {}", pattern, path.display());
+                Ok(true)
+            }
+            None => {
+                debug!("{} matched no files", pattern);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Parses the TOML file at `path`, relative to the app dir. `None` if it doesn't exist.
+    pub fn read_toml<T: DeserializeOwned, P: AsRef<Path>>(&self, path: P) -> Result<Option<T>> {
+        self.read_file(path.as_ref(), |contents| Ok(toml::from_str(contents)?))
+    }
+
+    /// Parses the JSON file at `path`, relative to the app dir. `None` if it doesn't exist.
+    pub fn read_json<T: DeserializeOwned, P: AsRef<Path>>(&self, path: P) -> Result<Option<T>> {
+        self.read_file(path.as_ref(), |contents| {
+            Ok(serde_json::from_str(contents)?)
         })
     }
 
+    /// Parses the YAML file at `path`, relative to the app dir. `None` if it doesn't exist.
+    pub fn read_yaml<T: DeserializeOwned, P: AsRef<Path>>(&self, path: P) -> Result<Option<T>> {
+        self.read_file(path.as_ref(), |contents| {
+            Ok(serde_yaml::from_str(contents)?)
+        })
+    }
+
+    fn read_file<T, F>(&self, path: &Path, parse: F) -> Result<Option<T>>
+    where
+        F: FnOnce(&str) -> Result<T>,
+    {
+        if !self.has_file(path) {
+            return Ok(None);
+        }
+
+        let full_path = self.root.join(path);
+        let contents = fs::read_to_string(&full_path).with_context(Operation::Read, &full_path)?;
+
+        parse(&contents)
+            .map(Some)
+            .map_err(|err| err.with_context(Operation::Parse, &full_path))
+    }
+
     pub fn fail(&self) -> i32 {
         debug!("Detection failed. Exiting with {}", FAIL_STATUS_CODE);
         FAIL_STATUS_CODE
@@ -43,8 +178,7 @@ impl Detect {
 
     pub fn pass(&self, build_plan: Option<&BuildPlan>) -> Result<i32> {
         if let Some(build_plan) = build_plan {
-            let toml_string = toml::to_string(build_plan)?;
-            fs::write(&self.build_plan_output, toml_string)?;
+            atomic::write_toml(&self.build_plan_output, build_plan)?;
         }
 
         debug!("Detection passed. Exiting with {}", PASS_STATUS_CODE);
@@ -56,12 +190,106 @@ impl Detect {
         debug!("Detection produced an error. Exiting with {}", code);
         code
     }
+
+    /// Prints `err` to stderr and returns the exit code it maps to. An unsupported stack fails
+    /// detection instead, `check_stack` has already written why.
+    pub fn report(&self, err: &Error) -> i32 {
+        match err.kind() {
+            ErrorKind::UnsupportedStack(_) | ErrorKind::MissingMixins(..) => self.fail(),
+            _ => self.error(err.report()),
+        }
+    }
+}
+
+/// Constructs a `Detect`, see `Detect::builder` and `Detect::from_env`.
+///
+/// The stack and platform are read using the builder's env unless they're given directly.
+#[derive(Default)]
+pub struct DetectBuilder {
+    inputs: lifecycle::Inputs,
+}
+
+impl DetectBuilder {
+    /// The lifecycle's env, used to read the stack and locate the service bindings.
+    pub fn env(mut self, env: Env) -> Self {
+        self.inputs.env = env;
+        self
+    }
+
+    pub fn app_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.inputs.app_dir = Some(dir.into());
+        self
+    }
+
+    pub fn stack(mut self, stack: Stack) -> Self {
+        self.inputs.stack = Some(stack);
+        self
+    }
+
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.inputs.platform = Some(platform);
+        self
+    }
+
+    pub fn platform_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.inputs.platform_dir = Some(dir.into());
+        self
+    }
+
+    /// Takes precedence over `plan_reader`.
+    pub fn build_plan(mut self, build_plan: BuildPlan) -> Self {
+        self.inputs.build_plan = Some(build_plan);
+        self
+    }
+
+    /// Reads the build plan TOML from the file at `path`. Takes precedence over `plan_reader`.
+    pub fn plan_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.inputs.plan_file = Some(path.into());
+        self
+    }
+
+    /// Reads the build plan TOML from `reader`. Without a plan or a reader the plan is empty.
+    pub fn plan_reader<R: Read + 'static>(mut self, reader: R) -> Self {
+        self.inputs.plan_reader = Some(Box::new(reader));
+        self
+    }
+
+    pub fn build_plan_output<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.inputs.build_plan_output = Some(path.into());
+        self
+    }
+
+    /// Defaults to the app's project.toml, if it has one.
+    pub fn project(mut self, project: Project) -> Self {
+        self.inputs.project = Some(project);
+        self
+    }
+
+    /// Defaults to `Output::new()`.
+    pub fn output(mut self, output: Output) -> Self {
+        self.inputs.output = Some(output);
+        self
+    }
+
+    pub fn build(self) -> Result<Detect> {
+        let context = self.inputs.resolve()?;
+
+        Ok(Detect {
+            root: context.root,
+            stack: context.stack,
+            platform: context.platform,
+            build_plan: context.build_plan,
+            build_plan_output: context.build_plan_output,
+            project: context.project,
+            output: context.output,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::build_plan::Dependency;
+    use crate::{build_plan::Dependency, buildpack};
     use failure::Error;
     use std::result::Result;
     use tempdir::TempDir;
@@ -82,9 +310,9 @@ mod tests {
         fs::create_dir_all(&platform_dir)?;
 
         Ok(Setup {
-            platform_dir: platform_dir,
-            build_plan: build_plan,
-            old_env_var: old_env_var,
+            platform_dir,
+            build_plan,
+            old_env_var,
             _tmpdir: tmpdir,
         })
     }
@@ -140,4 +368,158 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn it_checks_stack() -> Result<(), Error> {
+        let setup = setup()?;
+        let stdin = b"";
+        let mut detect = Detect::new(
+            setup.platform_dir,
+            setup.build_plan,
+            Some(Box::new(&stdin[..])),
+        )?;
+        reset_cnb_stack_id(setup.old_env_var);
+
+        let mut buildpack = Buildpack::new("heroku/ruby", "Heroku Ruby", "1.0.0");
+        buildpack.stacks.push(buildpack::Stack::new("heroku-18"));
+        let err = detect.check_stack(&buildpack).unwrap_err();
+        assert_eq!(detect.report(&err), 100);
+
+        buildpack.stacks.push(buildpack::Stack::new("*"));
+        assert!(detect.check_stack(&buildpack).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_builds_from_injected_inputs() -> Result<(), Error> {
+        let tmpdir = TempDir::new("detect")?;
+        let platform_dir = tmpdir.path().join("platform");
+        fs::create_dir_all(platform_dir.join("env"))?;
+        fs::write(platform_dir.join("env").join("RAILS_ENV"), "production")?;
+        let mut env = Env::new();
+        env.set_var("CNB_STACK_ID", "heroku-20");
+
+        let detect = Detect::builder()
+            .env(env)
+            .app_dir(tmpdir.path())
+            .platform_dir(&platform_dir)
+            .plan_reader(&b"[ruby]\nversion = \"2.6.5\""[..])
+            .build_plan_output(tmpdir.path().join("plan.toml"))
+            .build()?;
+
+        assert_eq!(*detect.stack, "heroku-20");
+        assert_eq!(detect.platform.env.var("RAILS_ENV")?, "production");
+        assert_eq!(detect.build_plan.get("ruby").unwrap().version, "2.6.5");
+
+        let missing = Detect::builder()
+            .stack(detect.stack)
+            .platform_dir(&platform_dir)
+            .build();
+        assert!(missing.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_args() -> Result<(), Error> {
+        let tmpdir = TempDir::new("detect")?;
+        let buildpack_dir = tmpdir.path().join("buildpack");
+        let platform_dir = tmpdir.path().join("platform");
+        fs::create_dir_all(&buildpack_dir)?;
+        fs::create_dir_all(&platform_dir)?;
+        fs::write(
+            buildpack_dir.join("buildpack.toml"),
+            r#"api = "0.2"
+
+[buildpack]
+id = "heroku/ruby"
+name = "Heroku Ruby"
+version = "1.0.0"
+"#,
+        )?;
+        let mut env = Env::new();
+        env.set_var("CNB_STACK_ID", "heroku-20");
+        env.set_var("CNB_BUILDPACK_DIR", &buildpack_dir);
+        let plan_path = tmpdir.path().join("plan.toml");
+        let args = vec![
+            buildpack_dir.join("bin").join("detect").into_os_string(),
+            platform_dir.into_os_string(),
+            plan_path.clone().into_os_string(),
+        ];
+
+        // newer APIs don't pass a build plan on stdin
+        let detect = Detect::from_args(env, args)?
+            .app_dir(tmpdir.path())
+            .build()?;
+
+        assert_eq!(detect.build_plan_output, plan_path);
+        assert!(detect.build_plan.is_empty());
+
+        Ok(())
+    }
+
+    fn detect_app(tmpdir: &Path, app_dir: &Path) -> Result<Detect, Error> {
+        let mut env = Env::new();
+        env.set_var("CNB_STACK_ID", "heroku-20");
+
+        Ok(Detect::builder()
+            .env(env)
+            .app_dir(app_dir)
+            .platform_dir(tmpdir.join("platform"))
+            .build_plan_output(tmpdir.join("plan.toml"))
+            .build()?)
+    }
+
+    #[test]
+    fn it_checks_app_files() -> Result<(), Error> {
+        let tmpdir = TempDir::new("detect")?;
+        let app_dir = tmpdir.path().join("app[1]");
+        fs::create_dir_all(app_dir.join("src"))?;
+        fs::write(app_dir.join("Gemfile"), "")?;
+        fs::write(app_dir.join("src").join("app.csproj"), "")?;
+        // matched by the app dir if it were treated as a glob
+        fs::create_dir_all(tmpdir.path().join("app1"))?;
+        fs::write(tmpdir.path().join("app1").join("package.json"), "{}")?;
+
+        let detect = detect_app(tmpdir.path(), &app_dir)?;
+
+        assert!(detect.has_file("Gemfile"));
+        assert!(!detect.has_file("package.json"));
+        assert!(detect.has_glob("**/*.csproj")?);
+        assert!(!detect.has_glob("*.json")?);
+        assert!(detect.has_glob("[").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_app_files() -> Result<(), Error> {
+        let tmpdir = TempDir::new("detect")?;
+        let app_dir = tmpdir.path().join("app");
+        fs::create_dir_all(&app_dir)?;
+        fs::write(app_dir.join("runtime.toml"), "ruby = \"2.6.5\"")?;
+        fs::write(app_dir.join("package.json"), r#"{"name": "app"}"#)?;
+        fs::write(app_dir.join("config.yml"), "name: app")?;
+        fs::write(app_dir.join("broken.json"), "{")?;
+
+        let detect = detect_app(tmpdir.path(), &app_dir)?;
+
+        let runtime: Option<toml::Value> = detect.read_toml("runtime.toml")?;
+        assert_eq!(runtime.unwrap()["ruby"].as_str(), Some("2.6.5"));
+        let package: Option<serde_json::Value> = detect.read_json("package.json")?;
+        assert_eq!(package.unwrap()["name"], "app");
+        let config: Option<serde_yaml::Value> = detect.read_yaml("config.yml")?;
+        assert_eq!(config.unwrap()["name"].as_str(), Some("app"));
+        let missing: Option<serde_json::Value> = detect.read_json("missing.json")?;
+        assert!(missing.is_none());
+
+        let err = detect
+            .read_json::<serde_json::Value, _>("broken.json")
+            .unwrap_err();
+        assert_eq!(err.operation(), Some(Operation::Parse));
+        assert_eq!(err.path(), Some(app_dir.join("broken.json").as_path()));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,316 @@
+//! Stable SHA-256 digests of directory trees, for telling whether their contents changed.
+//!
+//! The digest covers each entry's path relative to the root, its type and permission bits, the
+//! target of symlinks (which aren't followed) and the contents of files. Entries are visited in
+//! sorted order so the same tree always gives the same digest.
+
+use crate::error::{ErrorKind, Operation, Result, ResultExt};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+const DIGEST_PREFIX: &str = "sha256:";
+
+const FILE_TAG: u8 = b'f';
+const DIR_TAG: u8 = b'd';
+const SYMLINK_TAG: u8 = b'l';
+const OTHER_TAG: u8 = b'o';
+
+/// Computes the digest of a directory, ignoring modification times unless asked not to.
+///
+/// ```no_run
+/// use libbuildpack::digest::ContentDigest;
+///
+/// let digest = ContentDigest::new().mtimes(true).digest("/layers/ruby/gems")?;
+/// # Ok::<(), libbuildpack::error::Error>(())
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct ContentDigest {
+    mtimes: bool,
+}
+
+impl ContentDigest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Includes modification times, so touching a file changes the digest.
+    pub fn mtimes(mut self, mtimes: bool) -> Self {
+        self.mtimes = mtimes;
+        self
+    }
+
+    /// The digest of everything under `dir`, formatted as `sha256:<hex>`. A missing dir has the
+    /// same digest as an empty one.
+    pub fn digest<P: AsRef<Path>>(&self, dir: P) -> Result<String> {
+        let dir = dir.as_ref();
+        let mut hasher = Sha256::new();
+        if dir.is_dir() {
+            self.hash_dir(&mut hasher, dir, Path::new(""))?;
+        }
+
+        Ok(finish(hasher))
+    }
+
+    fn hash_dir(&self, hasher: &mut Sha256, dir: &Path, relative: &Path) -> Result<()> {
+        let mut entries = fs::read_dir(dir)
+            .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+            .with_context(Operation::ReadDir, dir)?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let relative = relative.join(entry.file_name());
+            let metadata = fs::symlink_metadata(&path).with_context(Operation::Read, &path)?;
+            let file_type = metadata.file_type();
+            let tag = if file_type.is_symlink() {
+                SYMLINK_TAG
+            } else if file_type.is_dir() {
+                DIR_TAG
+            } else if file_type.is_file() {
+                FILE_TAG
+            } else {
+                OTHER_TAG
+            };
+
+            hasher.update([tag]);
+            hash_bytes(hasher, relative.as_os_str().as_bytes());
+            hasher.update((metadata.mode() & 0o7777).to_be_bytes());
+            if self.mtimes && !file_type.is_dir() {
+                hasher.update(metadata.mtime().to_be_bytes());
+                hasher.update(metadata.mtime_nsec().to_be_bytes());
+            }
+
+            match tag {
+                SYMLINK_TAG => {
+                    let target = fs::read_link(&path).with_context(Operation::Read, &path)?;
+                    hash_bytes(hasher, target.as_os_str().as_bytes());
+                }
+                DIR_TAG => self.hash_dir(hasher, &path, &relative)?,
+                FILE_TAG => hash_file(hasher, &path, metadata.len())?,
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The digest of the files under `root` matching `patterns`, globs relative to `root`, formatted
+/// as `sha256:<hex>`. It covers the relative paths and contents of the files; matched dirs are
+/// included with everything under them. Patterns that match nothing are skipped.
+pub fn files<P: AsRef<Path>>(root: P, patterns: &[&str]) -> Result<String> {
+    let root = root.as_ref();
+    let mut paths = BTreeSet::new();
+    for pattern in patterns {
+        for entry in glob_in(root, pattern)? {
+            let path = match entry {
+                Ok(path) => path,
+                Err(err) => {
+                    let path = err.path().to_path_buf();
+                    return Err(io::Error::from(err)).with_context(Operation::Read, path);
+                }
+            };
+            paths.insert(path);
+        }
+    }
+
+    let content_digest = ContentDigest::new();
+    let mut hasher = Sha256::new();
+    for path in paths {
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        hash_bytes(&mut hasher, relative.as_os_str().as_bytes());
+        if path.is_dir() {
+            hasher.update([DIR_TAG]);
+            content_digest.hash_dir(&mut hasher, &path, relative)?;
+        } else {
+            let len = fs::metadata(&path)
+                .with_context(Operation::Read, &path)?
+                .len();
+            hasher.update([FILE_TAG]);
+            hash_file(&mut hasher, &path, len)?;
+        }
+    }
+
+    Ok(finish(hasher))
+}
+
+/// The paths matching `pattern`, a glob relative to `root`. `root` is matched literally, so
+/// dirs with glob characters in their names work.
+pub(crate) fn glob_in(root: &Path, pattern: &str) -> Result<glob::Paths> {
+    let escaped_root =
+        glob::Pattern::escape(root.to_str().ok_or_else(|| ErrorKind::new_path(root))?);
+    let full_pattern = Path::new(&escaped_root).join(pattern);
+    let full_pattern = full_pattern
+        .to_str()
+        .ok_or_else(|| ErrorKind::new_path(&full_pattern))?;
+
+    Ok(glob::glob(full_pattern)?)
+}
+
+fn hash_file(hasher: &mut Sha256, path: &Path, len: u64) -> Result<()> {
+    let mut file = File::open(path).with_context(Operation::Read, path)?;
+    hasher.update(len.to_be_bytes());
+    io::copy(&mut file, hasher).with_context(Operation::Read, path)?;
+
+    Ok(())
+}
+
+// length prefixed, so that adjacent fields can't run into each other
+fn hash_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_be_bytes());
+    hasher.update(bytes);
+}
+
+fn finish(hasher: Sha256) -> String {
+    let mut digest = String::from(DIGEST_PREFIX);
+    for byte in hasher.finalize() {
+        digest.push_str(&format!("{:02x}", byte));
+    }
+
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Error;
+    use std::{
+        os::unix::fs::{symlink, PermissionsExt},
+        result::Result,
+    };
+    use tempdir::TempDir;
+
+    fn fixture(dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(dir.join("bin"))?;
+        fs::write(dir.join("bin").join("ruby"), "#!/bin/sh")?;
+        fs::write(dir.join("VERSION"), "2.6.5")?;
+        symlink("bin/ruby", dir.join("ruby"))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_is_stable_across_copies() -> Result<(), Error> {
+        let tmpdir = TempDir::new("digest")?;
+        let first = tmpdir.path().join("first");
+        let second = tmpdir.path().join("second");
+        fixture(&first)?;
+        fixture(&second)?;
+
+        let digest = ContentDigest::new().digest(&first)?;
+
+        assert!(digest.starts_with("sha256:"));
+        assert_eq!(digest.len(), "sha256:".len() + 64);
+        assert_eq!(digest, ContentDigest::new().digest(&second)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_detects_changes() -> Result<(), Error> {
+        let tmpdir = TempDir::new("digest")?;
+        let dir = tmpdir.path().join("ruby");
+        fixture(&dir)?;
+        let content_digest = ContentDigest::new();
+        let original = content_digest.digest(&dir)?;
+
+        fs::write(dir.join("VERSION"), "2.7.0")?;
+        let contents = content_digest.digest(&dir)?;
+        assert_ne!(contents, original);
+
+        fs::remove_file(dir.join("ruby"))?;
+        symlink("bin", dir.join("ruby"))?;
+        let target = content_digest.digest(&dir)?;
+        assert_ne!(target, contents);
+
+        fs::set_permissions(
+            dir.join("bin").join("ruby"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+        assert_ne!(content_digest.digest(&dir)?, target);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_optionally_includes_mtimes() -> Result<(), Error> {
+        let tmpdir = TempDir::new("digest")?;
+        let first = tmpdir.path().join("first");
+        let second = tmpdir.path().join("second");
+        fs::create_dir_all(&first)?;
+        fs::create_dir_all(&second)?;
+        fs::write(first.join("VERSION"), "2.6.5")?;
+        fs::write(second.join("VERSION"), "2.6.5")?;
+        let old = std::time::SystemTime::UNIX_EPOCH;
+        File::options()
+            .write(true)
+            .open(first.join("VERSION"))?
+            .set_modified(old)?;
+
+        assert_eq!(
+            ContentDigest::new().digest(&first)?,
+            ContentDigest::new().digest(&second)?
+        );
+        assert_ne!(
+            ContentDigest::new().mtimes(true).digest(&first)?,
+            ContentDigest::new().mtimes(true).digest(&second)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_treats_missing_dirs_as_empty() -> Result<(), Error> {
+        let tmpdir = TempDir::new("digest")?;
+        let empty = tmpdir.path().join("empty");
+        fs::create_dir_all(&empty)?;
+
+        assert_eq!(
+            ContentDigest::new().digest(tmpdir.path().join("missing"))?,
+            ContentDigest::new().digest(&empty)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_digests_matching_files() -> Result<(), Error> {
+        let tmpdir = TempDir::new("digest")?;
+        let app = tmpdir.path().join("app[1]");
+        let app = app.as_path();
+        fs::create_dir_all(app)?;
+        // matched by the root if it were treated as a glob
+        fs::create_dir_all(tmpdir.path().join("app1"))?;
+        fs::write(tmpdir.path().join("app1").join("Gemfile.lock"), "GEM")?;
+        fs::write(app.join("Gemfile"), "source 'https://rubygems.org'")?;
+        fs::write(app.join("Gemfile.lock"), "GEM")?;
+        fs::write(app.join("app.rb"), "puts 'hi'")?;
+        let patterns = ["Gemfile*", "vendor/cache"];
+        let original = files(app, &patterns)?;
+
+        fs::write(
+            tmpdir.path().join("app1").join("Gemfile.lock"),
+            "GEM\n  remote",
+        )?;
+        assert_eq!(files(app, &patterns)?, original);
+
+        fs::write(app.join("app.rb"), "puts 'bye'")?;
+        assert_eq!(files(app, &patterns)?, original);
+
+        fs::write(app.join("Gemfile.lock"), "GEM\n  remote")?;
+        let lockfile = files(app, &patterns)?;
+        assert_ne!(lockfile, original);
+
+        fs::create_dir_all(app.join("vendor").join("cache"))?;
+        fs::write(app.join("vendor").join("cache").join("rack.gem"), "rack")?;
+        assert_ne!(files(app, &patterns)?, lockfile);
+
+        Ok(())
+    }
+}
@@ -1,29 +1,64 @@
+//! An environment that can be built up and inspected without touching the process env.
+
 use std::{
-    collections::HashMap,
-    env::VarError,
+    collections::{btree_map, BTreeMap},
+    env::{VarError, VarsOs},
     ffi::{OsStr, OsString},
+    fmt,
+    iter::FromIterator,
+    process::Command,
 };
 
-#[derive(Debug)]
+/// The env var `key` has a name or value that isn't valid unicode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotUnicode {
+    pub key: OsString,
+}
+
+impl fmt::Display for NotUnicode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Env var {} is not valid unicode",
+            self.key.to_string_lossy()
+        )
+    }
+}
+
+impl std::error::Error for NotUnicode {}
+
+/// A set of env vars, iterated in order of their names.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Env {
-    env: HashMap<OsString, OsString>,
+    env: BTreeMap<OsString, OsString>,
 }
 
 impl Env {
     pub fn new() -> Self {
         Self {
-            env: HashMap::new(),
+            env: BTreeMap::new(),
         }
     }
 
+    /// A snapshot of the current process environment.
+    pub fn from_process() -> Self {
+        Self::from(std::env::vars_os())
+    }
+
     pub fn clear(&mut self) {
         self.env.clear()
     }
 
-    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+    /// The vars as strings, in order. Fails on the first var whose name or value isn't valid
+    /// unicode, use `vars_os` to handle those.
+    pub fn vars(&self) -> Result<Vec<(&str, &str)>, NotUnicode> {
         self.env
             .iter()
-            .map(|(key, value)| (key.to_str().unwrap_or(""), value.to_str().unwrap_or("")))
+            .map(|(key, value)| match (key.to_str(), value.to_str()) {
+                (Some(key), Some(value)) => Ok((key, value)),
+                _ => Err(NotUnicode { key: key.clone() }),
+            })
+            .collect()
     }
 
     pub fn vars_os(&self) -> impl Iterator<Item = (&OsString, &OsString)> {
@@ -47,14 +82,77 @@ impl Env {
     pub fn var_os<K: AsRef<OsStr>>(&self, key: K) -> Option<OsString> {
         let key_as_ref = key.as_ref();
 
-        self.env.get(key_as_ref).map(|value| OsString::from(value))
+        self.env.get(key_as_ref).map(OsString::from)
     }
 
     pub fn set_var<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) {
         let key_os_string = key.as_ref().to_os_string();
         let value_os_string = value.as_ref().to_os_string();
 
-        &self.env.insert(key_os_string, value_os_string);
+        self.env.insert(key_os_string, value_os_string);
+    }
+
+    /// Removes `key`, returning its value if it was set.
+    pub fn remove_var<K: AsRef<OsStr>>(&mut self, key: K) -> Option<OsString> {
+        self.env.remove(key.as_ref())
+    }
+
+    pub fn contains<K: AsRef<OsStr>>(&self, key: K) -> bool {
+        self.env.contains_key(key.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.env.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty()
+    }
+
+    /// Makes this the whole environment of `command`, the process env isn't inherited.
+    pub fn apply_to<'a>(&self, command: &'a mut Command) -> &'a mut Command {
+        command.env_clear().envs(&self.env)
+    }
+}
+
+impl From<VarsOs> for Env {
+    fn from(vars: VarsOs) -> Self {
+        vars.collect()
+    }
+}
+
+impl<K: AsRef<OsStr>, V: AsRef<OsStr>> FromIterator<(K, V)> for Env {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut env = Self::new();
+        env.extend(iter);
+
+        env
+    }
+}
+
+impl<K: AsRef<OsStr>, V: AsRef<OsStr>> Extend<(K, V)> for Env {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.set_var(key, value);
+        }
+    }
+}
+
+impl IntoIterator for Env {
+    type Item = (OsString, OsString);
+    type IntoIter = btree_map::IntoIter<OsString, OsString>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.env.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Env {
+    type Item = (&'a OsString, &'a OsString);
+    type IntoIter = btree_map::Iter<'a, OsString, OsString>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.env.iter()
     }
 }
 
@@ -64,7 +162,7 @@ mod tests {
 
     #[test]
     fn it_gets_var() {
-        let mut internal_env = HashMap::new();
+        let mut internal_env = BTreeMap::new();
         internal_env.insert(OsString::from("FOO"), OsString::from("foo"));
         let mut env = Env { env: internal_env };
 
@@ -89,7 +187,7 @@ mod tests {
 
     #[test]
     fn it_gets_var_os() {
-        let mut internal_env = HashMap::new();
+        let mut internal_env = BTreeMap::new();
         internal_env.insert(OsString::from("FOO"), OsString::from("foo"));
         let mut env = Env { env: internal_env };
 
@@ -124,7 +222,7 @@ mod tests {
 
     #[test]
     fn it_iterates_vars_os() {
-        let mut internal_env = HashMap::new();
+        let mut internal_env = BTreeMap::new();
         internal_env.insert(OsString::from("FOO"), OsString::from("foo"));
         let env = Env { env: internal_env };
 
@@ -136,16 +234,79 @@ mod tests {
 
     #[test]
     fn it_iterates_vars() {
-        let mut internal_env = HashMap::new();
+        let mut internal_env = BTreeMap::new();
         internal_env.insert(OsString::from("FOO"), OsString::from("foo"));
         let env = Env { env: internal_env };
 
-        for (key, value) in env.vars() {
+        for (key, value) in env.vars().unwrap() {
             assert_eq!(key, String::from("FOO"));
             assert_eq!(value, String::from("foo"));
         }
     }
 
+    #[test]
+    fn it_reports_non_unicode_vars() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut env = Env::new();
+        env.set_var("FOO", "foo");
+        env.set_var("BAR", OsString::from_vec(vec![0xff, 0xfe]));
+
+        let err = env.vars().unwrap_err();
+        assert_eq!(err.key, OsString::from("BAR"));
+        assert_eq!(err.to_string(), "Env var BAR is not valid unicode");
+        assert_eq!(env.vars_os().count(), 2);
+    }
+
+    #[test]
+    fn it_removes_vars() {
+        let mut env = Env::new();
+        env.set_var("FOO", "foo");
+
+        assert!(env.contains("FOO"));
+        assert_eq!(env.remove_var("FOO"), Some(OsString::from("foo")));
+        assert!(!env.contains("FOO"));
+        assert_eq!(env.remove_var("FOO"), None);
+    }
+
+    #[test]
+    fn it_iterates_in_order() {
+        let mut env: Env = vec![("ZED", "z"), ("ALPHA", "a")].into_iter().collect();
+        env.extend(vec![("MIDDLE", "m")]);
+
+        let keys: Vec<&str> = env
+            .vars()
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["ALPHA", "MIDDLE", "ZED"]);
+        let pairs: Vec<(OsString, OsString)> = env.into_iter().collect();
+        assert_eq!(pairs[0], (OsString::from("ALPHA"), OsString::from("a")));
+    }
+
+    #[test]
+    fn it_converts_from_process_env() {
+        let env = Env::from(std::env::vars_os());
+
+        // other tests change CNB_STACK_ID, PATH is left alone
+        assert_eq!(env.var_os("PATH"), std::env::var_os("PATH"));
+    }
+
+    #[test]
+    fn it_applies_to_commands() -> std::io::Result<()> {
+        let mut env = Env::new();
+        env.set_var("GREETING", "hello");
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", "echo \"$GREETING $HOME\""]);
+
+        let output = env.apply_to(&mut command).output()?;
+
+        assert_eq!(output.stdout, b"hello \n");
+
+        Ok(())
+    }
+
     #[test]
     fn it_clears() {
         let mut env = Env::new();
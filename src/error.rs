@@ -1,48 +1,116 @@
-use failure::{Backtrace, Context, Fail};
+mod user;
+use crate::command::CommandFailure;
 use std::{
     fmt,
     path::{Path, PathBuf},
 };
 
+pub use user::UserError;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 /// An error that can occur while running a buildpack.
 pub struct Error {
-    ctx: Context<ErrorKind>,
+    kind: ErrorKind,
+    // the file or directory being accessed when the error occurred
+    path: Option<PathBuf>,
+    operation: Option<Operation>,
 }
 
 impl Error {
     pub fn kind(&self) -> &ErrorKind {
-        self.ctx.get_context()
+        &self.kind
     }
-}
 
-impl Fail for Error {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.ctx.cause()
+    /// The file or directory that was being accessed, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
     }
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.ctx.backtrace()
+    /// The operation that was attempted on `path`, if any.
+    pub fn operation(&self) -> Option<Operation> {
+        self.operation
+    }
+
+    pub(crate) fn with_context<P: AsRef<Path>>(mut self, operation: Operation, path: P) -> Error {
+        self.operation = Some(operation);
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// The exit code of a `UserError`, `1` for every other error.
+    pub fn exit_code(&self) -> i32 {
+        match self.kind() {
+            ErrorKind::User(err) => err.exit_code,
+            _ => 1,
+        }
+    }
+
+    /// Prints the error to stderr in the same boxed format as a `UserError` and returns the exit
+    /// code to use.
+    pub fn report(&self) -> i32 {
+        match self.kind() {
+            ErrorKind::User(err) => err.report(),
+            _ => UserError::new("Internal buildpack error", self.chain_message()).report(),
+        }
+    }
+
+    // the message of the error and each of its sources, "Could not read X: permission denied"
+    fn chain_message(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            message.push_str(": ");
+            message.push_str(&err.to_string());
+            source = err.source();
+        }
+
+        message
     }
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.ctx.fmt(f)
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.kind {
+            ErrorKind::Io(ref err) => Some(err),
+            ErrorKind::TomlSer(ref err) => Some(err),
+            ErrorKind::TomlDe(ref err) => Some(err),
+            ErrorKind::Env(ref err) => Some(err),
+            ErrorKind::NotUnicode(ref err) => Some(err),
+            ErrorKind::Json(ref err) => Some(err),
+            ErrorKind::Yaml(ref err) => Some(err),
+            ErrorKind::Glob(ref err) => Some(err),
+            _ => None,
+        }
     }
 }
 
-impl From<Context<ErrorKind>> for Error {
-    fn from(ctx: Context<ErrorKind>) -> Error {
-        Error { ctx }
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the underlying error is left to `source()`, so that it isn't repeated in error chains
+        match (&self.operation, &self.path) {
+            (Some(operation), Some(path)) => {
+                write!(f, "Could not {} {}", operation, path.display())
+            }
+            _ => self.kind.fmt(f),
+        }
     }
 }
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
-        Error::from(Context::new(kind))
+        Error {
+            kind,
+            path: None,
+            operation: None,
+        }
+    }
+}
+
+impl From<UserError> for Error {
+    fn from(err: UserError) -> Error {
+        Error::from(ErrorKind::User(Box::new(err)))
     }
 }
 
@@ -64,12 +132,36 @@ impl From<toml::de::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::from(ErrorKind::Json(err))
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Error {
+        Error::from(ErrorKind::Yaml(err))
+    }
+}
+
+impl From<glob::PatternError> for Error {
+    fn from(err: glob::PatternError) -> Error {
+        Error::from(ErrorKind::Glob(err))
+    }
+}
+
 impl From<std::env::VarError> for Error {
     fn from(err: std::env::VarError) -> Error {
         Error::from(ErrorKind::Env(err))
     }
 }
 
+impl From<crate::env::NotUnicode> for Error {
+    fn from(err: crate::env::NotUnicode) -> Error {
+        Error::from(ErrorKind::NotUnicode(err))
+    }
+}
+
 impl From<std::ffi::OsString> for Error {
     fn from(os_string: std::ffi::OsString) -> Error {
         Error::from(ErrorKind::OsString(os_string))
@@ -78,6 +170,7 @@ impl From<std::ffi::OsString> for Error {
 
 /// The specific kind of error that can occur.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ErrorKind {
     /// A path contains invalid unicode.
     PathUnicode(PathBuf),
@@ -89,15 +182,79 @@ pub enum ErrorKind {
     TomlSer(toml::ser::Error),
     /// Toml Deserialization error.
     TomlDe(toml::de::Error),
+    /// JSON Deserialization error.
+    Json(serde_json::Error),
+    /// YAML Deserialization error.
+    Yaml(serde_yaml::Error),
+    /// An invalid glob pattern.
+    Glob(glob::PatternError),
     /// Env Var fetching error.
     Env(std::env::VarError),
+    /// An env var isn't valid unicode.
+    NotUnicode(crate::env::NotUnicode),
     /// OsString contains invalid Unicode data
     OsString(std::ffi::OsString),
     /// No
     NoArgs,
-    /// Hints that destructuring should not be exhaustive.
-    #[doc(hidden)]
-    __Nonexhaustive,
+    /// A required input wasn't given to a builder.
+    MissingInput(&'static str),
+    /// An error to be shown to the user.
+    User(Box<UserError>),
+    /// A name that can't be used as an env var in a shell script.
+    InvalidEnvName(String),
+    /// A `BP_*` configuration value doesn't match its declared type.
+    InvalidConfig(String, String),
+    /// A subprocess exited with a non-zero status.
+    CommandFailed(Box<CommandFailure>),
+    /// The current stack isn't listed in buildpack.toml.
+    UnsupportedStack(String),
+    /// The current stack doesn't provide the mixins required by buildpack.toml.
+    MissingMixins(String, Vec<String>),
+}
+
+/// The filesystem operation that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    Read,
+    Write,
+    CreateDir,
+    ReadDir,
+    Remove,
+    /// Parsing the contents of a file.
+    Parse,
+    /// Serializing the contents of a file before writing it.
+    Serialize,
+    /// Running a program.
+    Execute,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operation = match *self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::CreateDir => "create directory",
+            Operation::ReadDir => "read directory",
+            Operation::Remove => "remove",
+            Operation::Parse => "parse",
+            Operation::Serialize => "serialize",
+            Operation::Execute => "execute",
+        };
+
+        f.write_str(operation)
+    }
+}
+
+/// Attaches the operation and path being accessed to the error of a failed operation.
+pub(crate) trait ResultExt<T> {
+    fn with_context<P: AsRef<Path>>(self, operation: Operation, path: P) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn with_context<P: AsRef<Path>>(self, operation: Operation, path: P) -> Result<T> {
+        self.map_err(|err| err.into().with_context(operation, path))
+    }
 }
 
 impl ErrorKind {
@@ -112,16 +269,104 @@ impl fmt::Display for ErrorKind {
             ErrorKind::PathUnicode(ref path) => {
                 write!(f, "Path contains invalid unicode: {}", path.display())
             }
-            ErrorKind::Io(ref err) => err.fmt(f),
+            ErrorKind::Io(_) => write!(f, "I/O error"),
             ErrorKind::FileNotFound(ref expected_file) => {
                 write!(f, "File Not Found: {}", expected_file.display())
             }
-            ErrorKind::TomlSer(ref err) => err.fmt(f),
-            ErrorKind::TomlDe(ref err) => err.fmt(f),
-            ErrorKind::Env(ref err) => err.fmt(f),
+            ErrorKind::TomlSer(_) => write!(f, "Could not serialize TOML"),
+            ErrorKind::TomlDe(_) => write!(f, "Could not parse TOML"),
+            ErrorKind::Json(_) => write!(f, "Could not parse JSON"),
+            ErrorKind::Yaml(_) => write!(f, "Could not parse YAML"),
+            ErrorKind::Glob(_) => write!(f, "Invalid glob pattern"),
+            ErrorKind::Env(_) => write!(f, "Could not read env var"),
+            ErrorKind::NotUnicode(_) => write!(f, "Invalid env var"),
             ErrorKind::OsString(ref _os_string) => write!(f, "invalid unicode characters provided"),
             ErrorKind::NoArgs => write!(f, "Not enough args passed"),
-            ErrorKind::__Nonexhaustive => panic!("invalid error"),
+            ErrorKind::MissingInput(input) => write!(f, "Missing required input: {}", input),
+            ErrorKind::User(ref err) => err.fmt(f),
+            ErrorKind::InvalidEnvName(ref name) => write!(f, "Invalid env var name: {:?}", name),
+            ErrorKind::InvalidConfig(ref name, ref message) => {
+                write!(f, "Invalid configuration {}: {}", name, message)
+            }
+            ErrorKind::CommandFailed(ref failure) => failure.fmt(f),
+            ErrorKind::UnsupportedStack(ref id) => write!(f, "Unsupported stack: {}", id),
+            ErrorKind::MissingMixins(ref id, ref mixins) => write!(
+                f,
+                "Stack {} is missing required mixins: {}",
+                id,
+                mixins.join(", ")
+            ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn it_displays_the_operation_and_path() {
+        let err = Error::from(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .with_context(Operation::Write, "/layers/foo.toml");
+
+        assert_eq!(err.path(), Some(Path::new("/layers/foo.toml")));
+        assert_eq!(err.operation(), Some(Operation::Write));
+        assert_eq!(err.to_string(), "Could not write /layers/foo.toml");
+    }
+
+    #[test]
+    fn it_chains_the_source() {
+        let result: std::result::Result<(), std::io::Error> =
+            Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        let err = result
+            .with_context(Operation::Read, "/layers/foo.toml")
+            .unwrap_err();
+
+        let source = err.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn it_converts_into_boxed_std_error() {
+        let err: Box<dyn StdError + Send + Sync> = Error::from(ErrorKind::NoArgs).into();
+
+        assert_eq!(err.to_string(), "Not enough args passed");
+    }
+
+    #[test]
+    fn it_doesnt_repeat_messages_in_the_source_chain() {
+        let result: std::result::Result<(), std::io::Error> = Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "permission denied",
+        ));
+        let err = result
+            .with_context(Operation::Read, "/layers/foo.toml")
+            .unwrap_err();
+
+        let mut messages = vec![err.to_string()];
+        let mut source = err.source();
+        while let Some(err) = source {
+            messages.push(err.to_string());
+            source = err.source();
+        }
+
+        assert_eq!(
+            messages,
+            vec!["Could not read /layers/foo.toml", "permission denied"]
+        );
+        assert_eq!(
+            err.chain_message(),
+            "Could not read /layers/foo.toml: permission denied"
+        );
+
+        let err = Error::from(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "permission denied",
+        ));
+        assert_eq!(err.chain_message(), "I/O error: permission denied");
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+
+const DEFAULT_EXIT_CODE: i32 = 1;
+
+/// An error meant for the person running the build, as opposed to the buildpack author.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserError {
+    pub title: String,
+    pub detail: String,
+    pub hint: Option<String>,
+    pub docs_url: Option<String>,
+    pub exit_code: i32,
+}
+
+impl UserError {
+    pub fn new<T: Into<String>, D: Into<String>>(title: T, detail: D) -> Self {
+        Self {
+            title: title.into(),
+            detail: detail.into(),
+            hint: None,
+            docs_url: None,
+            exit_code: DEFAULT_EXIT_CODE,
+        }
+    }
+
+    pub fn with_hint<H: Into<String>>(mut self, hint: H) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    pub fn with_docs_url<U: Into<String>>(mut self, docs_url: U) -> Self {
+        self.docs_url = Some(docs_url.into());
+        self
+    }
+
+    pub fn with_exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// Renders the error inside a box, ready to be printed to stderr.
+    pub fn render(&self) -> String {
+        let mut lines = vec![format!("ERROR: {}", self.title)];
+
+        if !self.detail.is_empty() {
+            lines.push(String::new());
+            lines.extend(self.detail.lines().map(|line| line.to_string()));
+        }
+        if self.hint.is_some() || self.docs_url.is_some() {
+            lines.push(String::new());
+        }
+        if let Some(hint) = &self.hint {
+            lines.push(format!("Hint: {}", hint));
+        }
+        if let Some(docs_url) = &self.docs_url {
+            lines.push(format!("Docs: {}", docs_url));
+        }
+
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        let border = format!("+{}+", "-".repeat(width + 2));
+        let mut output = String::new();
+
+        output.push_str(&border);
+        output.push('\n');
+        for line in lines {
+            let padding = " ".repeat(width - line.chars().count());
+            output.push_str(&format!("| {}{} |\n", line, padding));
+        }
+        output.push_str(&border);
+        output.push('\n');
+
+        output
+    }
+
+    /// Prints the rendered error to stderr and returns the exit code to use.
+    pub fn report(&self) -> i32 {
+        eprint!("{}", self.render());
+
+        self.exit_code
+    }
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.title, self.detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_a_box() {
+        let error = UserError::new("Ruby version not found", "Could not find ruby 2.6.99")
+            .with_hint("Check the version in your Gemfile")
+            .with_docs_url("https://example.com/ruby");
+
+        assert_eq!(
+            error.render(),
+            r#"+-----------------------------------------+
+| ERROR: Ruby version not found           |
+|                                         |
+| Could not find ruby 2.6.99              |
+|                                         |
+| Hint: Check the version in your Gemfile |
+| Docs: https://example.com/ruby          |
++-----------------------------------------+
+"#
+        );
+    }
+
+    #[test]
+    fn it_renders_without_optional_fields() {
+        let error = UserError::new("Failed", "");
+
+        assert_eq!(
+            error.render(),
+            r#"+---------------+
+| ERROR: Failed |
++---------------+
+"#
+        );
+    }
+
+    #[test]
+    fn it_defaults_exit_code() {
+        assert_eq!(UserError::new("Failed", "").exit_code, 1);
+        assert_eq!(
+            UserError::new("Failed", "").with_exit_code(42).exit_code,
+            42
+        );
+    }
+}
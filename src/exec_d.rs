@@ -0,0 +1,77 @@
+//! Runtime helpers for `exec.d` executables.
+//!
+//! The launcher runs every executable installed with `Layer::install_exec_d` before the app
+//! process, and reads the env vars it writes as TOML to file descriptor 3:
+//!
+//! ```no_run
+//! let concurrency = std::env::var("WEB_CONCURRENCY").unwrap_or_else(|_| "2".to_string());
+//! libbuildpack::exec_d::write_env(vec![("WEB_CONCURRENCY", concurrency)]).unwrap();
+//! ```
+
+use crate::error::{Operation, Result, ResultExt};
+use std::{collections::BTreeMap, fs::OpenOptions, io::Write};
+
+// fd 3, reopened through its path so that the process's own fd 3 is never closed
+const OUTPUT_PATH: &str = "/dev/fd/3";
+
+/// Writes `vars` to fd 3 for the launcher to set in the process env. It's an error if fd 3 isn't
+/// open, e.g. when the executable isn't run by the launcher.
+pub fn write_env<I, K, V>(vars: I) -> Result<()>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    let output = OpenOptions::new()
+        .append(true)
+        .open(OUTPUT_PATH)
+        .with_context(Operation::Write, OUTPUT_PATH)?;
+
+    write_env_to(output, vars)
+}
+
+/// Writes `vars` as the TOML table the launcher expects.
+pub fn write_env_to<W, I, K, V>(mut writer: W, vars: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+{
+    let vars: BTreeMap<String, String> = vars
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect();
+    let toml_string = toml::to_string(&vars)?;
+    writer.write_all(toml_string.as_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Error;
+    use std::result::Result;
+
+    #[test]
+    fn it_writes_env_as_toml() -> Result<(), Error> {
+        let mut buf = Vec::new();
+
+        write_env_to(
+            &mut buf,
+            vec![("WEB_CONCURRENCY", "4"), ("GREETING", "say \"hi\"")],
+        )?;
+
+        let toml_string = String::from_utf8(buf)?;
+        assert_eq!(
+            toml_string,
+            "GREETING = \"say \\\"hi\\\"\"\nWEB_CONCURRENCY = \"4\"\n"
+        );
+        let vars: BTreeMap<String, String> = toml::from_str(&toml_string)?;
+        assert_eq!(vars["GREETING"], "say \"hi\"");
+
+        Ok(())
+    }
+}
@@ -2,13 +2,16 @@ mod config;
 mod env;
 mod launch;
 mod layer;
-use crate::error::{Error, ErrorKind, Result};
+mod profile;
+use crate::{
+    atomic,
+    error::{Error, ErrorKind, Result},
+};
+pub use env::{EnvSet, Envs};
 use launch::Launch;
 pub use layer::Layer;
-use std::{
-    fs,
-    path::{Path, PathBuf},
-};
+pub use profile::{shell_escape, ProfileScript};
+use std::path::{Path, PathBuf};
 
 const ROOT_LAYER_FOLDER: &str = "/layers";
 const LAUNCH_TOML_FILE: &str = "launch.toml";
@@ -41,8 +44,8 @@ impl Layers {
     }
 
     pub fn write_launch(&self) -> Result<()> {
-        let string = toml::to_string(&self.launch)?;
-        fs::write(&self.launch_path(), string)?;
+        let path = self.launch_path();
+        atomic::write_toml(&path, &self.launch)?;
 
         Ok(())
     }
@@ -56,7 +59,7 @@ pub fn new(name: &str) -> Result<Layer> {
 mod tests {
     use super::*;
     use failure::Error;
-    use std::result::Result;
+    use std::{fs, result::Result};
     use tempdir::TempDir;
 
     #[test]
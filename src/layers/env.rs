@@ -1,6 +1,8 @@
 use crate::env::Env;
 
-#[derive(Debug)]
+const PATH_DELIMITER: &str = ":";
+
+#[derive(Debug, Default)]
 pub struct Envs {
     pub build: EnvSet,
     pub launch: EnvSet,
@@ -17,7 +19,7 @@ impl Envs {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct EnvSet {
     pub append: Env,
     pub r#override: Env,
@@ -38,6 +40,27 @@ impl EnvSet {
         self.r#override.clear();
         self.append_path.clear();
     }
+
+    /// Applies the modifications to `env` the way the lifecycle does: `override` replaces the
+    /// value, `append` adds to the end of it and `append_path` adds a path entry in front of it.
+    pub fn apply(&self, env: &mut Env) {
+        for (key, value) in self.r#override.vars_os() {
+            env.set_var(key, value);
+        }
+        for (key, value) in self.append.vars_os() {
+            let mut new_value = env.var_os(key).unwrap_or_default();
+            new_value.push(value);
+            env.set_var(key, new_value);
+        }
+        for (key, value) in self.append_path.vars_os() {
+            let mut new_value = value.clone();
+            if let Some(existing) = env.var_os(key).filter(|existing| !existing.is_empty()) {
+                new_value.push(PATH_DELIMITER);
+                new_value.push(existing);
+            }
+            env.set_var(key, new_value);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -58,4 +81,24 @@ mod tests {
         assert_eq!(env_set.append_path.var("FOO"), Err(VarError::NotPresent));
         assert_eq!(env_set.r#override.var("FOO"), Err(VarError::NotPresent));
     }
+
+    #[test]
+    fn it_applies_env_set() {
+        let mut env_set = EnvSet::new();
+        env_set.r#override.set_var("RAILS_ENV", "production");
+        env_set.append.set_var("RUBYOPT", " -W0");
+        env_set.append_path.set_var("PATH", "/layers/ruby/bin");
+        env_set.append_path.set_var("GEM_PATH", "/layers/gems");
+        let mut env = Env::new();
+        env.set_var("RAILS_ENV", "development");
+        env.set_var("RUBYOPT", "-rbundler/setup");
+        env.set_var("PATH", "/usr/bin");
+
+        env_set.apply(&mut env);
+
+        assert_eq!(env.var("RAILS_ENV"), Ok("production".to_string()));
+        assert_eq!(env.var("RUBYOPT"), Ok("-rbundler/setup -W0".to_string()));
+        assert_eq!(env.var("PATH"), Ok("/layers/ruby/bin:/usr/bin".to_string()));
+        assert_eq!(env.var("GEM_PATH"), Ok("/layers/gems".to_string()));
+    }
 }
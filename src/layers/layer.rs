@@ -2,14 +2,41 @@ use super::{
     config::Config,
     env::{EnvSet, Envs},
 };
-use crate::error::Result;
+use crate::{
+    atomic,
+    digest::ContentDigest,
+    error::{Operation, Result, ResultExt},
+};
 use log::debug;
-use std::{ffi::OsStr, fs, path::PathBuf};
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{symlink, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
 use toml;
 
 const BUILD_ENV_FOLDER: &str = "env.build";
 const LAUNCH_ENV_FOLDER: &str = "env.launch";
 const SHARED_ENV_FOLDER: &str = "env";
+const EXEC_D_FOLDER: &str = "exec.d";
+const BIN_FOLDER: &str = "bin";
+const LIB_FOLDER: &str = "lib";
+const INCLUDE_FOLDER: &str = "include";
+const PKGCONFIG_FOLDER: &str = "lib/pkgconfig";
+// (layer subdirectory, env var, set during the build, set at launch)
+const DERIVED_ENV: &[(&str, &str, bool, bool)] = &[
+    (BIN_FOLDER, "PATH", true, true),
+    (LIB_FOLDER, "LD_LIBRARY_PATH", true, true),
+    (LIB_FOLDER, "LIBRARY_PATH", true, false),
+    (INCLUDE_FOLDER, "CPATH", true, false),
+    (PKGCONFIG_FOLDER, "PKG_CONFIG_PATH", true, false),
+];
+const EXECUTABLE_MODE: u32 = 0o755;
+const CONTENT_DIGEST_KEY: &str = "content_digest";
 
 #[derive(Debug)]
 pub struct Layer {
@@ -23,8 +50,8 @@ pub struct Layer {
 impl Layer {
     pub fn new(root: &str, name: &str) -> Result<Self> {
         let root_path = PathBuf::from(&root);
-        let layer_path = root_path.join(&name);
-        fs::create_dir_all(&layer_path)?;
+        let layer_path = root_path.join(name);
+        fs::create_dir_all(&layer_path).with_context(Operation::CreateDir, &layer_path)?;
 
         Ok(Layer {
             root: root_path,
@@ -56,9 +83,115 @@ impl Layer {
         self.layer_path().join("profile.d")
     }
 
+    /// Added to `PATH` by the lifecycle.
+    pub fn bin_path(&self) -> PathBuf {
+        self.layer_path().join(BIN_FOLDER)
+    }
+
+    /// Added to `LD_LIBRARY_PATH`, and `LIBRARY_PATH` during the build, by the lifecycle.
+    pub fn lib_path(&self) -> PathBuf {
+        self.layer_path().join(LIB_FOLDER)
+    }
+
+    /// Added to `CPATH` during the build by the lifecycle.
+    pub fn include_path(&self) -> PathBuf {
+        self.layer_path().join(INCLUDE_FOLDER)
+    }
+
+    /// Added to `PKG_CONFIG_PATH` during the build by the lifecycle.
+    pub fn pkgconfig_path(&self) -> PathBuf {
+        self.layer_path().join(PKGCONFIG_FOLDER)
+    }
+
+    /// Symlinks the executable at `source` as `bin/<name>`, replacing an existing one.
+    pub fn link_bin<P: AsRef<Path>>(&self, name: &str, source: P) -> Result<PathBuf> {
+        let bin_path = self.bin_path();
+        fs::create_dir_all(&bin_path).with_context(Operation::CreateDir, &bin_path)?;
+        let link_path = bin_path.join(name);
+        if fs::symlink_metadata(&link_path).is_ok() {
+            fs::remove_file(&link_path).with_context(Operation::Remove, &link_path)?;
+        }
+        debug!(
+            "Linking {} => {}",
+            source.as_ref().display(),
+            link_path.display()
+        );
+        symlink(source.as_ref(), &link_path).with_context(Operation::Write, &link_path)?;
+
+        Ok(link_path)
+    }
+
+    /// Copies the executable at `source` into `bin/<name>`.
+    pub fn copy_bin<P: AsRef<Path>>(&self, name: &str, source: P) -> Result<PathBuf> {
+        Self::install_executable(&self.bin_path(), name, source.as_ref())
+    }
+
+    /// The env the lifecycle derives from the layer's `bin`, `lib`, `include` and `lib/pkgconfig`
+    /// dirs for the build, if the layer is a build layer and the dirs exist.
+    pub fn derived_build_env(&self) -> EnvSet {
+        self.derived_env(self.config.build, |(_, _, build, _)| *build)
+    }
+
+    /// The env the lifecycle derives from the layer's dirs at launch, if the layer is a launch
+    /// layer and the dirs exist.
+    pub fn derived_launch_env(&self) -> EnvSet {
+        self.derived_env(self.config.launch, |(_, _, _, launch)| *launch)
+    }
+
+    fn derived_env<F>(&self, enabled: bool, applies: F) -> EnvSet
+    where
+        F: Fn(&&(&str, &str, bool, bool)) -> bool,
+    {
+        let mut env = EnvSet::new();
+        if !enabled {
+            return env;
+        }
+
+        for (folder, key, _, _) in DERIVED_ENV.iter().filter(applies) {
+            let path = self.layer_path().join(folder);
+            if path.is_dir() {
+                env.append_path.set_var(key, path);
+            }
+        }
+
+        env
+    }
+
+    pub fn exec_d_path(&self) -> PathBuf {
+        self.layer_path().join(EXEC_D_FOLDER)
+    }
+
+    /// Copies the executable at `source` into `exec.d/<name>`, so the launcher runs it before
+    /// every process. See `libbuildpack::exec_d` for writing its env vars.
+    pub fn install_exec_d<P: AsRef<Path>>(&self, name: &str, source: P) -> Result<PathBuf> {
+        Self::install_executable(&self.exec_d_path(), name, source.as_ref())
+    }
+
+    /// Copies the executable at `source` into `exec.d/<process>/<name>`, so the launcher only runs
+    /// it before the `process` process type.
+    pub fn install_process_exec_d<P: AsRef<Path>>(
+        &self,
+        process: &str,
+        name: &str,
+        source: P,
+    ) -> Result<PathBuf> {
+        Self::install_executable(&self.exec_d_path().join(process), name, source.as_ref())
+    }
+
+    fn install_executable(dir: &Path, name: &str, source: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir).with_context(Operation::CreateDir, dir)?;
+        let file_path = dir.join(name);
+        debug!("Installing {} => {}", source.display(), file_path.display());
+        fs::copy(source, &file_path).with_context(Operation::Write, &file_path)?;
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(EXECUTABLE_MODE))
+            .with_context(Operation::Write, &file_path)?;
+
+        Ok(file_path)
+    }
+
     pub fn write_metadata(&self) -> Result<()> {
-        let string = toml::to_string(&self.config)?;
-        fs::write(&self.config_path(), string)?;
+        let path = self.config_path();
+        atomic::write_toml(&path, &self.config)?;
 
         Ok(())
     }
@@ -67,8 +200,8 @@ impl Layer {
         let path = self.config_path();
 
         if path.exists() {
-            let contents = fs::read_to_string(&path)?;
-            self.config = toml::from_str(&contents)?;
+            let contents = fs::read_to_string(&path).with_context(Operation::Read, &path)?;
+            self.config = toml::from_str(&contents).with_context(Operation::Parse, &path)?;
 
             debug!(
                 "Reading layer metadata: {} => {:#?}",
@@ -86,7 +219,7 @@ impl Layer {
         let path = self.config_path();
 
         if path.is_file() {
-            fs::remove_file(&self.config_path())?;
+            fs::remove_file(&path).with_context(Operation::Remove, &path)?;
         } else {
             debug!("Metadata {} does not exist", &path.display());
         }
@@ -94,13 +227,53 @@ impl Layer {
         Ok(())
     }
 
+    /// The digest of the layer dir's contents, see [`ContentDigest`].
+    pub fn content_digest(&self, digest: &ContentDigest) -> Result<String> {
+        digest.digest(self.layer_path())
+    }
+
+    /// Stores the current content digest in the layer's metadata and writes it.
+    pub fn store_content_digest(&mut self, digest: &ContentDigest) -> Result<String> {
+        let content_digest = self.content_digest(digest)?;
+        self.config
+            .metadata
+            .insert(CONTENT_DIGEST_KEY, content_digest.as_str());
+        self.write_metadata()?;
+
+        Ok(content_digest)
+    }
+
+    /// The content digest stored by [`store_content_digest`](Self::store_content_digest).
+    pub fn stored_content_digest(&self) -> Option<&str> {
+        self.config
+            .metadata
+            .get(CONTENT_DIGEST_KEY)
+            .and_then(|value| value.as_str())
+    }
+
+    /// Whether the contents differ from the stored digest. Layers without one count as changed.
+    pub fn content_changed(&self, digest: &ContentDigest) -> Result<bool> {
+        match self.stored_content_digest() {
+            Some(stored) => Ok(stored != self.content_digest(digest)?),
+            None => Ok(true),
+        }
+    }
+
     pub fn write_profile_d(&self, name: &str, contents: &str) -> Result<()> {
-        let profile_d_path = self.profile_d_path();
+        Self::write_profile(&self.profile_d_path(), name, contents)
+    }
 
-        fs::create_dir_all(&profile_d_path)?;
+    /// Writes a script into `profile.d/<process>/`, only sourced for the `process` process type.
+    /// Use `ProfileScript` to build `contents`.
+    pub fn write_process_profile_d(&self, process: &str, name: &str, contents: &str) -> Result<()> {
+        Self::write_profile(&self.profile_d_path().join(process), name, contents)
+    }
+
+    fn write_profile(profile_d_path: &Path, name: &str, contents: &str) -> Result<()> {
+        fs::create_dir_all(profile_d_path).with_context(Operation::CreateDir, profile_d_path)?;
         let file_path = profile_d_path.join(name);
         debug!("Writing profile: {} <= {}", &file_path.display(), contents);
-        fs::write(&file_path, contents)?;
+        fs::write(&file_path, contents).with_context(Operation::Write, &file_path)?;
 
         Ok(())
     }
@@ -119,34 +292,28 @@ impl Layer {
             ))
     }
 
-    fn write_env(layer_path: &PathBuf, folder: &str, env: &EnvSet) -> Result<()> {
+    fn write_env(layer_path: &Path, folder: &str, env: &EnvSet) -> Result<()> {
         let folder_path = layer_path.join(folder);
-        fs::create_dir_all(&folder_path)?;
-
-        for (key, value) in env.append_path.vars() {
-            debug!(
-                "Writing environment variable to {}: {} => {}",
-                folder, key, value
-            );
-            fs::write(&folder_path.join(key), value)?;
-        }
-
-        for (key, value) in env.append.vars() {
-            let filename = format!("{}.append", key);
-            debug!(
-                "Writing environment variable to {}: {} => {}",
-                folder, filename, value
-            );
-            fs::write(&folder_path.join(filename), value)?;
-        }
-
-        for (key, value) in env.r#override.vars() {
-            let filename = format!("{}.override", key);
-            debug!(
-                "Writing environment variable to {}: {} => {}",
-                folder, filename, value
-            );
-            fs::write(&folder_path.join(filename), value)?;
+        fs::create_dir_all(&folder_path).with_context(Operation::CreateDir, &folder_path)?;
+
+        for (suffix, env) in &[
+            ("", &env.append_path),
+            (".append", &env.append),
+            (".override", &env.r#override),
+        ] {
+            // keys and values are written as raw bytes, they don't have to be valid unicode
+            for (key, value) in env.vars_os() {
+                let mut filename = key.clone();
+                filename.push(suffix);
+                debug!(
+                    "Writing environment variable to {}: {} => {}",
+                    folder,
+                    filename.to_string_lossy(),
+                    value.to_string_lossy()
+                );
+                let path = folder_path.join(filename);
+                fs::write(&path, value.as_bytes()).with_context(Operation::Write, &path)?;
+            }
         }
 
         Ok(())
@@ -166,7 +333,7 @@ impl Layer {
             ))
     }
 
-    fn read_env(layer: &PathBuf, folder: &str, env: &mut EnvSet) -> Result<()> {
+    fn read_env(layer: &Path, folder: &str, env: &mut EnvSet) -> Result<()> {
         env.clear();
 
         let folder_path = layer.join(folder);
@@ -174,10 +341,11 @@ impl Layer {
             return Ok(());
         }
 
-        for entry in fs::read_dir(folder_path)? {
-            let entry = entry?;
+        for entry in fs::read_dir(&folder_path).with_context(Operation::ReadDir, &folder_path)? {
+            let entry = entry.with_context(Operation::ReadDir, &folder_path)?;
             let env_path = entry.path();
-            let value = fs::read_to_string(entry.path())?;
+            let value = fs::read(&env_path).with_context(Operation::Read, &env_path)?;
+            let value = OsString::from_vec(value);
 
             let ext = env_path.extension().unwrap_or(OsStr::new(""));
             let mut key_path = entry.path();
@@ -223,15 +391,15 @@ mod tests {
         let layer = Layer::new(root_path.to_str().unwrap(), &name)?;
 
         Ok(Setup {
-            root_path: root_path,
+            root_path,
             _tmp_dir: tmp_dir,
-            name: name,
-            layer: layer,
+            name,
+            layer,
         })
     }
 
     fn test_env_file(env_file: &PathBuf, value: &str) -> Result<(), Error> {
-        let contents = fs::read_to_string(&env_file)?;
+        let contents = fs::read_to_string(env_file)?;
 
         assert_eq!(contents, value);
 
@@ -260,9 +428,9 @@ mod tests {
             })
             .is_ok());
 
-        assert_eq!(true, layer.config.launch);
-        assert_eq!(true, layer.config.build);
-        assert_eq!(false, layer.config.cache);
+        assert!(layer.config.launch);
+        assert!(layer.config.build);
+        assert!(!layer.config.cache);
         assert!(&setup.root_path.join("foo.toml").exists());
 
         Ok(())
@@ -282,11 +450,11 @@ mod tests {
         assert!(layer.write_metadata().is_ok());
         assert!(toml_file.exists());
 
-        let contents = fs::read_to_string(&toml_file)?;
+        let contents = fs::read_to_string(toml_file)?;
         let config: Config = toml::from_str(&contents)?;
-        assert_eq!(config.launch, true);
-        assert_eq!(config.build, false);
-        assert_eq!(config.cache, false);
+        assert!(config.launch);
+        assert!(!config.build);
+        assert!(!config.cache);
         assert_eq!(config.metadata.get("foo").unwrap().as_str(), Some("bar"));
 
         Ok(())
@@ -297,7 +465,7 @@ mod tests {
         let mut setup = setup()?;
 
         fs::write(
-            &setup.root_path.join("foo.toml"),
+            setup.root_path.join("foo.toml"),
             r#"
 launch = true
 build = false
@@ -310,9 +478,9 @@ foo = "bar"
         let layer = &mut setup.layer;
         let read = layer.read_metadata();
         assert!(read.is_ok());
-        assert_eq!(layer.config.launch, true);
-        assert_eq!(layer.config.build, false);
-        assert_eq!(layer.config.cache, false);
+        assert!(layer.config.launch);
+        assert!(!layer.config.build);
+        assert!(!layer.config.cache);
         assert_eq!(
             layer.config.metadata.get("foo").unwrap().as_str(),
             Some("bar")
@@ -463,6 +631,26 @@ foo = "bar"
         Ok(())
     }
 
+    #[test]
+    fn it_writes_non_unicode_env_vars() -> Result<(), Error> {
+        let mut setup = setup()?;
+        let layer = &mut setup.layer;
+        let value = OsString::from_vec(vec![b'f', 0xff, b'o']);
+        layer.envs.build.r#override.set_var("FOO", &value);
+
+        layer.write_envs()?;
+
+        let env_folder = setup.root_path.join(&setup.name).join("env.build");
+        assert_eq!(
+            fs::read(env_folder.join("FOO.override"))?,
+            vec![b'f', 0xff, b'o']
+        );
+        layer.read_envs()?;
+        assert_eq!(layer.envs.build.r#override.var_os("FOO"), Some(value));
+
+        Ok(())
+    }
+
     #[test]
     fn it_removes_metadata_that_does_not_exist() -> Result<(), Error> {
         let mut setup = setup()?;
@@ -496,6 +684,20 @@ version = 1
         Ok(())
     }
 
+    #[test]
+    fn it_reports_path_on_failed_write() -> Result<(), Error> {
+        let setup = setup()?;
+        let layer = &setup.layer;
+        let profile_d_path = setup.root_path.join("foo").join("profile.d");
+        fs::write(&profile_d_path, "")?;
+
+        let err = layer.write_profile_d("foo.sh", "exit 0").unwrap_err();
+        assert_eq!(err.path(), Some(profile_d_path.as_path()));
+        assert_eq!(err.operation(), Some(Operation::CreateDir));
+
+        Ok(())
+    }
+
     #[test]
     fn it_writes_profile_d() -> Result<(), Error> {
         let setup = setup()?;
@@ -507,4 +709,150 @@ version = 1
 
         Ok(())
     }
+
+    #[test]
+    fn it_writes_process_profile_d() -> Result<(), Error> {
+        let setup = setup()?;
+        let layer = &setup.layer;
+        let mut script = crate::layers::ProfileScript::new();
+        script.export("WEB_CONCURRENCY", "2")?;
+
+        layer.write_process_profile_d("web", "concurrency.sh", &script.to_string())?;
+
+        test_env_file(
+            &setup
+                .root_path
+                .join("foo")
+                .join("profile.d")
+                .join("web")
+                .join("concurrency.sh"),
+            "export WEB_CONCURRENCY='2'\n",
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_links_and_copies_bins() -> Result<(), Error> {
+        let setup = setup()?;
+        let layer = &setup.layer;
+        let source = setup.root_path.join("ruby");
+        fs::write(&source, "#!/bin/sh")?;
+
+        let link = layer.link_bin("ruby", &source)?;
+        assert_eq!(fs::read_link(&link)?, source);
+        // relinking replaces the existing link
+        layer.link_bin("ruby", &source)?;
+
+        let copy = layer.copy_bin("irb", &source)?;
+        assert_eq!(copy, layer.bin_path().join("irb"));
+        assert_eq!(fs::metadata(&copy)?.permissions().mode() & 0o777, 0o755);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_derives_env_from_dirs() -> Result<(), Error> {
+        let mut setup = setup()?;
+        let layer = &mut setup.layer;
+        fs::create_dir_all(layer.bin_path())?;
+        fs::create_dir_all(layer.pkgconfig_path())?;
+
+        assert!(layer.derived_build_env().append_path.is_empty());
+
+        layer.config.build = true;
+        layer.config.launch = true;
+        let build = layer.derived_build_env();
+        let keys: Vec<_> = build
+            .append_path
+            .vars()?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            keys,
+            vec!["LD_LIBRARY_PATH", "LIBRARY_PATH", "PATH", "PKG_CONFIG_PATH"]
+        );
+        assert_eq!(
+            build.append_path.var_os("PATH"),
+            Some(layer.bin_path().into_os_string())
+        );
+
+        let launch = layer.derived_launch_env();
+        let keys: Vec<_> = launch
+            .append_path
+            .vars()?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["LD_LIBRARY_PATH", "PATH"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_installs_exec_d() -> Result<(), Error> {
+        let setup = setup()?;
+        let layer = &setup.layer;
+        let source = setup.root_path.join("helper");
+        fs::write(&source, "#!/bin/sh")?;
+        let exec_d_path = setup.root_path.join("foo").join("exec.d");
+
+        let installed = layer.install_exec_d("helper", &source)?;
+        assert_eq!(installed, exec_d_path.join("helper"));
+        assert_eq!(
+            fs::metadata(&installed)?.permissions().mode() & 0o777,
+            0o755
+        );
+
+        let installed = layer.install_process_exec_d("web", "helper", &source)?;
+        assert_eq!(installed, exec_d_path.join("web").join("helper"));
+        test_env_file(&installed, "#!/bin/sh")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_no_stray_files_on_failed_writes() -> Result<(), Error> {
+        let setup = setup()?;
+        let layer = &setup.layer;
+        let exec_d_path = setup.root_path.join("foo").join("exec.d");
+        let profile_d_path = setup.root_path.join("foo").join("profile.d");
+        fs::create_dir_all(profile_d_path.join("foo.sh"))?;
+
+        assert!(layer
+            .install_exec_d("helper", setup.root_path.join("missing"))
+            .is_err());
+        assert!(layer.write_profile_d("foo.sh", "exit 0").is_err());
+
+        assert_eq!(fs::read_dir(&exec_d_path)?.count(), 0);
+        let entries = fs::read_dir(&profile_d_path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(entries, vec![OsString::from("foo.sh")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stores_and_compares_content_digests() -> Result<(), Error> {
+        let setup = setup()?;
+        let mut layer = setup.layer;
+        let digest = ContentDigest::new();
+        fs::write(layer.layer_path().join("VERSION"), "2.6.5")?;
+
+        assert!(layer.content_changed(&digest)?);
+
+        let stored = layer.store_content_digest(&digest)?;
+        assert!(!layer.content_changed(&digest)?);
+
+        let mut cached = Layer::new(setup.root_path.to_str().unwrap(), &setup.name)?;
+        cached.read_metadata()?;
+        assert_eq!(cached.stored_content_digest(), Some(stored.as_str()));
+
+        fs::write(cached.layer_path().join("VERSION"), "2.7.0")?;
+        assert!(cached.content_changed(&digest)?);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,173 @@
+use crate::error::{ErrorKind, Result};
+use std::fmt;
+
+/// Builds a `profile.d` script out of shell-escaped env var modifications.
+///
+/// ```
+/// use libbuildpack::layers::ProfileScript;
+///
+/// let mut script = ProfileScript::new();
+/// script
+///     .export("RAILS_ENV", "production")?
+///     .prepend_path("PATH", "/layers/heroku_ruby/gems/bin")?;
+///
+/// assert_eq!(
+///     script.to_string(),
+///     "export RAILS_ENV='production'\nexport PATH='/layers/heroku_ruby/gems/bin'\"${PATH:+:$PATH}\"\n"
+/// );
+/// # Ok::<(), libbuildpack::error::Error>(())
+/// ```
+///
+/// Env var names have to match `[A-Za-z_][A-Za-z0-9_]*`, anything else is an error.
+#[derive(Debug, Default, Clone)]
+pub struct ProfileScript {
+    lines: Vec<String>,
+}
+
+impl ProfileScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn export(&mut self, key: &str, value: &str) -> Result<&mut Self> {
+        let key = env_name(key)?;
+        self.lines
+            .push(format!("export {}={}", key, shell_escape(value)));
+        Ok(self)
+    }
+
+    /// Exports `key` only if it isn't already set, so the user can override it.
+    pub fn export_default(&mut self, key: &str, value: &str) -> Result<&mut Self> {
+        let key = env_name(key)?;
+        self.lines.push(format!(
+            "[ -n \"${{{key}:-}}\" ] || export {key}={}",
+            shell_escape(value),
+            key = key
+        ));
+        Ok(self)
+    }
+
+    /// Adds `value` to the front of the `:` delimited list in `key`, e.g. `PATH`. The delimiter is
+    /// only added if `key` is set, as an empty entry would mean the current dir.
+    pub fn prepend_path(&mut self, key: &str, value: &str) -> Result<&mut Self> {
+        let key = env_name(key)?;
+        self.lines.push(format!(
+            "export {key}={}\"${{{key}:+:${key}}}\"",
+            shell_escape(value),
+            key = key
+        ));
+        Ok(self)
+    }
+
+    /// Adds `value` to the end of the `:` delimited list in `key`.
+    pub fn append_path(&mut self, key: &str, value: &str) -> Result<&mut Self> {
+        let key = env_name(key)?;
+        self.lines.push(format!(
+            "export {key}=\"${{{key}:+${key}:}}\"{}",
+            shell_escape(value),
+            key = key
+        ));
+        Ok(self)
+    }
+
+    /// Adds a line as is, it isn't escaped.
+    pub fn raw(&mut self, line: &str) -> &mut Self {
+        self.lines.push(line.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+impl fmt::Display for ProfileScript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+// only names the shell accepts, so they can go into the script unquoted
+fn env_name(key: &str) -> Result<&str> {
+    let mut chars = key.chars();
+    let valid = match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+
+    if valid {
+        Ok(key)
+    } else {
+        Err(ErrorKind::InvalidEnvName(key.to_string()).into())
+    }
+}
+
+/// Single quotes `value`, so the shell doesn't expand anything in it.
+pub fn shell_escape(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Error;
+    use std::{process::Command, result::Result};
+
+    #[test]
+    fn it_escapes_values() {
+        assert_eq!(shell_escape("simple"), "'simple'");
+        assert_eq!(shell_escape("it's $HOME"), "'it'\\''s $HOME'");
+    }
+
+    #[test]
+    fn it_runs_in_a_shell() -> Result<(), Error> {
+        let mut script = ProfileScript::new();
+        script
+            .export("GREETING", "it's \"$HOME\" `pwd`")?
+            .export_default("RAILS_ENV", "production")?
+            .export_default("RACK_ENV", "production")?
+            .prepend_path("GEM_PATH", "/layers/gems")?
+            .append_path("GEM_PATH", "/layers/vendor")?
+            .prepend_path("LD_LIBRARY_PATH", "/layers/lib")?
+            .append_path("PKG_CONFIG_PATH", "/layers/pkgconfig")?;
+        let script = format!(
+            "{}echo \"$GREETING|$RAILS_ENV|$RACK_ENV|$GEM_PATH|$LD_LIBRARY_PATH|$PKG_CONFIG_PATH\"",
+            script
+        );
+
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&script)
+            .env("RACK_ENV", "staging")
+            .env("GEM_PATH", "/usr/gems")
+            .env_remove("LD_LIBRARY_PATH")
+            .env_remove("PKG_CONFIG_PATH")
+            .output()?;
+
+        assert_eq!(
+            String::from_utf8(output.stdout)?,
+            "it's \"$HOME\" `pwd`|production|staging|/layers/gems:/usr/gems:/layers/vendor|/layers/lib|/layers/pkgconfig\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_env_names() {
+        let mut script = ProfileScript::new();
+
+        assert!(script.export("_RAILS_ENV2", "production").is_ok());
+        assert!(script.export("RAILS ENV", "production").is_err());
+        assert!(script.export_default("2FA", "on").is_err());
+        assert!(script.prepend_path("PATH;rm", "/bin").is_err());
+        assert!(script.append_path("", "/bin").is_err());
+        assert_eq!(script.to_string(), "export _RAILS_ENV2='production'\n");
+    }
+}
@@ -1,13 +1,24 @@
+mod atomic;
 mod build;
 mod detect;
 
 pub mod build_plan;
 pub mod buildpack;
-pub use build::Build;
-pub use detect::Detect;
-pub(crate) mod env;
+pub mod command;
+pub mod config;
+pub mod digest;
+pub use build::{Build, BuildBuilder};
+pub use detect::{Detect, DetectBuilder};
+pub mod env;
 pub mod error;
+pub mod exec_d;
 pub mod layers;
-pub(crate) mod metadata;
+pub mod lifecycle;
+pub mod logger;
+pub mod metadata;
+pub mod output;
 pub mod platform;
+pub mod project;
 pub mod stack;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
@@ -0,0 +1,214 @@
+//! Inputs the lifecycle passes to `bin/detect` and `bin/build`, either as env vars (newer
+//! lifecycles) or as positional arguments.
+
+use crate::{
+    build_plan::BuildPlan,
+    buildpack::Buildpack,
+    env::Env,
+    error::{Error, ErrorKind, Operation, Result, ResultExt},
+    output::Output,
+    platform::Platform,
+    project::Project,
+    stack::Stack,
+};
+use std::{
+    ffi::OsString,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+pub(crate) const BUILDPACK_FILE: &str = "buildpack.toml";
+
+pub(crate) const BUILDPACK_DIR_ENV_VAR: &str = "CNB_BUILDPACK_DIR";
+pub(crate) const LAYERS_DIR_ENV_VAR: &str = "CNB_LAYERS_DIR";
+pub(crate) const PLATFORM_DIR_ENV_VAR: &str = "CNB_PLATFORM_DIR";
+/// The buildpack plan passed to build.
+pub(crate) const BP_PLAN_PATH_ENV_VAR: &str = "CNB_BP_PLAN_PATH";
+/// Where detect writes its build plan.
+pub(crate) const BUILD_PLAN_PATH_ENV_VAR: &str = "CNB_BUILD_PLAN_PATH";
+
+/// How the lifecycle passes the build plan to a buildpack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanConvention {
+    /// The original API: the build plan is written to stdin and the plan argument is where the
+    /// buildpack writes its plan.
+    Stdin,
+    /// Buildpack API 0.2 and later: nothing is written to stdin, detect writes its build plan to
+    /// the plan argument and build reads the buildpack plan from it.
+    File,
+}
+
+impl PlanConvention {
+    /// The convention for the buildpack API version declared in buildpack.toml. Versions that
+    /// can't be parsed are assumed to be newer than any known version.
+    pub fn from_api(api: Option<&str>) -> Self {
+        let api = match api {
+            Some(api) => api,
+            None => return PlanConvention::Stdin,
+        };
+
+        let mut parts = api.trim().splitn(2, '.').map(|part| part.parse::<u32>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor))) if (major, minor) < (0, 2) => PlanConvention::Stdin,
+            _ => PlanConvention::File,
+        }
+    }
+
+    /// The convention for the buildpack in `buildpack_dir`. Without a buildpack.toml the
+    /// original API is assumed.
+    pub(crate) fn from_buildpack_dir(buildpack_dir: Option<&Path>) -> Result<Self> {
+        match buildpack_dir.map(|dir| dir.join(BUILDPACK_FILE)) {
+            Some(path) if path.is_file() => {
+                let buildpack = Buildpack::from_file(path)?;
+                Ok(Self::from_api(buildpack.api.as_deref()))
+            }
+            _ => Ok(PlanConvention::Stdin),
+        }
+    }
+}
+
+/// The path in `key` if the lifecycle set it, otherwise the positional argument at `index`.
+/// Empty values are treated as missing.
+pub(crate) fn path(env: &Env, key: &str, args: &[OsString], index: usize) -> Option<PathBuf> {
+    env.var_os(key)
+        .or_else(|| args.get(index).cloned())
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The buildpack dir in `CNB_BUILDPACK_DIR`, otherwise the parent of the `bin` dir holding the
+/// executable in `argv[0]`.
+pub(crate) fn buildpack_dir(env: &Env, args: &[OsString]) -> Option<PathBuf> {
+    if let Some(dir) = env
+        .var_os(BUILDPACK_DIR_ENV_VAR)
+        .filter(|dir| !dir.is_empty())
+    {
+        return Some(PathBuf::from(dir));
+    }
+
+    let mut buildpack_dir = PathBuf::from(args.first()?);
+    buildpack_dir.pop();
+    buildpack_dir.pop();
+
+    Some(buildpack_dir)
+}
+
+/// The inputs detect and build have in common, set through `DetectBuilder` and `BuildBuilder`.
+#[derive(Default)]
+pub(crate) struct Inputs {
+    pub(crate) env: Env,
+    pub(crate) app_dir: Option<PathBuf>,
+    pub(crate) stack: Option<Stack>,
+    pub(crate) platform: Option<Platform>,
+    pub(crate) platform_dir: Option<PathBuf>,
+    pub(crate) build_plan: Option<BuildPlan>,
+    pub(crate) plan_file: Option<PathBuf>,
+    pub(crate) plan_reader: Option<Box<dyn Read>>,
+    pub(crate) build_plan_output: Option<PathBuf>,
+    pub(crate) project: Option<Project>,
+    pub(crate) output: Option<Output>,
+}
+
+/// `Inputs` with the missing ones read using the env, or defaulted.
+pub(crate) struct Context {
+    pub(crate) root: PathBuf,
+    pub(crate) stack: Stack,
+    pub(crate) platform: Platform,
+    pub(crate) build_plan: BuildPlan,
+    pub(crate) build_plan_output: PathBuf,
+    pub(crate) project: Option<Project>,
+    pub(crate) output: Output,
+}
+
+impl Inputs {
+    /// The build plan is taken from `build_plan`, `plan_file` or `plan_reader`, in that order.
+    /// The app's project.toml env is merged into the platform env.
+    pub(crate) fn resolve(self) -> Result<Context> {
+        let build_plan = match (self.build_plan, self.plan_file, self.plan_reader) {
+            (Some(build_plan), _, _) => build_plan,
+            (None, Some(path), _) => {
+                let buf = fs::read_to_string(&path).with_context(Operation::Read, &path)?;
+                toml::from_str(&buf).with_context(Operation::Parse, &path)?
+            }
+            (None, None, Some(mut reader)) => {
+                let mut buf = String::new();
+                reader.read_to_string(&mut buf)?;
+                toml::from_str(&buf)?
+            }
+            (None, None, None) => BuildPlan::default(),
+        };
+        let stack = match self.stack {
+            Some(stack) => stack,
+            None => Stack::from_env(&self.env)?,
+        };
+        let mut platform = match (self.platform, self.platform_dir) {
+            (Some(platform), _) => platform,
+            (None, Some(dir)) => Platform::with_env(dir, &self.env)?,
+            (None, None) => return Err(Error::from(ErrorKind::MissingInput("platform dir"))),
+        };
+        let root = self.app_dir.ok_or(ErrorKind::MissingInput("app dir"))?;
+        let project = match self.project {
+            Some(project) => Some(project),
+            None => Project::from_dir(&root)?,
+        };
+        if let Some(project) = &project {
+            project.merge_env(&mut platform.env);
+        }
+        let build_plan_output = self
+            .build_plan_output
+            .ok_or(ErrorKind::MissingInput("build plan output"))?;
+
+        Ok(Context {
+            root,
+            stack,
+            platform,
+            build_plan,
+            build_plan_output,
+            project,
+            output: self.output.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn it_selects_plan_convention_by_api() {
+        assert_eq!(PlanConvention::from_api(None), PlanConvention::Stdin);
+        assert_eq!(PlanConvention::from_api(Some("0.1")), PlanConvention::Stdin);
+        assert_eq!(PlanConvention::from_api(Some("0.2")), PlanConvention::File);
+        assert_eq!(PlanConvention::from_api(Some("0.10")), PlanConvention::File);
+        assert_eq!(PlanConvention::from_api(Some("1.0")), PlanConvention::File);
+    }
+
+    #[test]
+    fn it_prefers_env_vars_over_args() {
+        let args = args(&["/cnb/buildpacks/ruby/bin/build", "/layers", "/platform"]);
+        let mut env = Env::new();
+
+        assert_eq!(
+            path(&env, LAYERS_DIR_ENV_VAR, &args, 1),
+            Some(PathBuf::from("/layers"))
+        );
+        assert_eq!(path(&env, BP_PLAN_PATH_ENV_VAR, &args, 3), None);
+        assert_eq!(
+            buildpack_dir(&env, &args),
+            Some(PathBuf::from("/cnb/buildpacks/ruby"))
+        );
+
+        env.set_var(LAYERS_DIR_ENV_VAR, "/tmp/layers");
+        env.set_var(BUILDPACK_DIR_ENV_VAR, "/tmp/ruby");
+        assert_eq!(
+            path(&env, LAYERS_DIR_ENV_VAR, &args, 1),
+            Some(PathBuf::from("/tmp/layers"))
+        );
+        assert_eq!(buildpack_dir(&env, &args), Some(PathBuf::from("/tmp/ruby")));
+    }
+}
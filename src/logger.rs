@@ -0,0 +1,199 @@
+use crate::{env::Env, platform::Platform};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::io::Write;
+
+const LOG_LEVEL_ENV_VAR: &str = "BP_LOG_LEVEL";
+const DEBUG_ENV_VAR: &str = "BP_DEBUG";
+const REDACTED: &str = "[REDACTED]";
+// env var names containing any of these are considered secrets
+const SECRET_MARKERS: &[&str] = &[
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "TOKEN",
+    "KEY",
+    "CREDENTIAL",
+    "AUTH",
+    "PRIVATE",
+];
+// shorter values would redact unrelated parts of the message
+const MIN_SECRET_LENGTH: usize = 4;
+
+/// A `log` backend writing to stderr, configured through `BP_LOG_LEVEL` and `BP_DEBUG`.
+#[derive(Debug)]
+pub struct Logger {
+    level: LevelFilter,
+    secrets: Vec<String>,
+}
+
+impl Logger {
+    /// `BP_DEBUG` enables debug logging, otherwise `BP_LOG_LEVEL` is used, defaulting to `info`.
+    /// Values of env vars that look like secrets are redacted from every message.
+    pub fn from_env(env: &Env) -> Self {
+        let level = if env
+            .var(DEBUG_ENV_VAR)
+            .map(|value| is_truthy(&value))
+            .unwrap_or(false)
+        {
+            LevelFilter::Debug
+        } else {
+            env.var(LOG_LEVEL_ENV_VAR)
+                .ok()
+                .and_then(|level| level.trim().parse().ok())
+                .unwrap_or(LevelFilter::Info)
+        };
+
+        // a secret that isn't unicode can't show up in a log message
+        let mut secrets: Vec<String> = env
+            .vars_os()
+            .filter_map(|(key, value)| Some((key.to_str()?, value.to_str()?)))
+            .filter(|(key, value)| is_secret(key) && value.len() >= MIN_SECRET_LENGTH)
+            .map(|(_, value)| value.to_string())
+            .collect();
+        // redact the longest values first, in case one secret contains another
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+        Self { level, secrets }
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Installs the logger as the global `log` backend.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_logger(Box::leak(Box::new(self)))
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let mut message = record.args().to_string();
+        for secret in &self.secrets {
+            message = message.replace(secret.as_str(), REDACTED);
+        }
+
+        let prefix = match record.level() {
+            Level::Error => "ERROR",
+            Level::Warn => "WARNING",
+            Level::Info => return message,
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+
+        format!("{}: {}", prefix, message)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let _ = writeln!(std::io::stderr(), "{}", self.format(record));
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Installs the logger configured from the process environment.
+pub fn init() -> Result<(), SetLoggerError> {
+    Logger::from_env(&Env::from_process()).init()
+}
+
+/// Installs the logger configured from the process environment, with the platform's env vars
+/// taking precedence.
+pub fn init_with_platform(platform: &Platform) -> Result<(), SetLoggerError> {
+    let mut env = Env::from_process();
+    for (key, value) in platform.env.vars_os() {
+        env.set_var(key, value);
+    }
+
+    Logger::from_env(&env).init()
+}
+
+fn is_truthy(value: &str) -> bool {
+    let value = value.trim();
+
+    !(value.is_empty() || value == "0" || value.eq_ignore_ascii_case("false"))
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_uppercase();
+
+    SECRET_MARKERS.iter().any(|marker| key.contains(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(logger: &Logger, level: Level, message: &str) -> String {
+        logger.format(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .build(),
+        )
+    }
+
+    #[test]
+    fn it_defaults_to_info() {
+        let logger = Logger::from_env(&Env::new());
+
+        assert_eq!(logger.level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn it_reads_log_level() {
+        let mut env = Env::new();
+        env.set_var("BP_LOG_LEVEL", "WARN");
+        assert_eq!(Logger::from_env(&env).level(), LevelFilter::Warn);
+
+        env.set_var("BP_LOG_LEVEL", "nonsense");
+        assert_eq!(Logger::from_env(&env).level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn it_reads_bp_debug() {
+        let mut env = Env::new();
+        env.set_var("BP_LOG_LEVEL", "error");
+        env.set_var("BP_DEBUG", "true");
+        assert_eq!(Logger::from_env(&env).level(), LevelFilter::Debug);
+
+        env.set_var("BP_DEBUG", "false");
+        assert_eq!(Logger::from_env(&env).level(), LevelFilter::Error);
+    }
+
+    #[test]
+    fn it_formats_messages() {
+        let logger = Logger::from_env(&Env::new());
+
+        assert_eq!(format(&logger, Level::Info, "hello"), "hello");
+        assert_eq!(format(&logger, Level::Warn, "hello"), "WARNING: hello");
+        assert_eq!(format(&logger, Level::Debug, "hello"), "DEBUG: hello");
+    }
+
+    #[test]
+    fn it_redacts_secrets() {
+        let mut env = Env::new();
+        env.set_var("AWS_SECRET_ACCESS_KEY", "abcd1234");
+        env.set_var("github_token", "ghp_xyz");
+        env.set_var("API_KEY", "1");
+        env.set_var("RUBY_VERSION", "2.6.5");
+
+        let logger = Logger::from_env(&env);
+        assert_eq!(
+            format(
+                &logger,
+                Level::Info,
+                "abcd1234 ghp_xyz ruby 2.6.5 on port 1"
+            ),
+            "[REDACTED] [REDACTED] ruby 2.6.5 on port 1"
+        );
+    }
+}
@@ -2,6 +2,7 @@ use serde_derive::{Deserialize, Serialize};
 use std::ops::{Deref, DerefMut};
 use toml::{map::Map, value::Value};
 
+/// A free-form TOML table, like `[metadata]` in buildpack.toml, project.toml and layer files.
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Metadata(Map<String, Value>);
 
@@ -0,0 +1,235 @@
+use std::{
+    fmt,
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+const NO_COLOR_ENV_VAR: &str = "NO_COLOR";
+const HEADER_PREFIX: &str = "-----> ";
+const STEP_INDENT: &str = "       ";
+const BLOCK_PREFIX: &str = " !     ";
+
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Formats buildpack output in a consistent style: headers, indented steps, timed steps and
+/// warning and error blocks.
+///
+/// Writes are best effort, a buildpack shouldn't fail because its output couldn't be written.
+pub struct Output {
+    writer: Box<dyn Write + Send>,
+    color: bool,
+}
+
+impl Output {
+    /// Writes to stdout, colored when stdout is a terminal and `NO_COLOR` isn't set.
+    pub fn new() -> Self {
+        let color = io::stdout().is_terminal() && std::env::var_os(NO_COLOR_ENV_VAR).is_none();
+
+        Self::from_writer(io::stdout(), color)
+    }
+
+    pub fn from_writer<W: Write + Send + 'static>(writer: W, color: bool) -> Self {
+        Self {
+            writer: Box::new(writer),
+            color,
+        }
+    }
+
+    pub fn header(&mut self, text: &str) {
+        let line = format!("{}{}", HEADER_PREFIX, text);
+        let line = self.paint(BOLD, &line);
+        self.write_line(&line);
+    }
+
+    /// Writes every line of `text` indented under the current header. Empty text is written as an
+    /// empty line.
+    pub fn step(&mut self, text: &str) {
+        if text.is_empty() {
+            self.write_line(STEP_INDENT);
+        }
+        for line in text.lines() {
+            self.write_line(&format!("{}{}", STEP_INDENT, line));
+        }
+    }
+
+    /// Runs `f` as a step, printing "<text> ... done (3.2s)" or "<text> ... failed (3.2s)".
+    pub fn timed<T, E, F>(&mut self, text: &str, f: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        let _ = write!(self.writer, "{}{} ... ", STEP_INDENT, text);
+        let _ = self.writer.flush();
+
+        let start = Instant::now();
+        let result = f();
+        let elapsed = format_duration(start.elapsed());
+
+        let status = match result {
+            Ok(_) => self.paint(GREEN, "done"),
+            Err(_) => self.paint(RED, "failed"),
+        };
+        self.write_line(&format!("{} ({})", status, elapsed));
+
+        result
+    }
+
+    pub fn warning(&mut self, title: &str, body: &str) {
+        self.block(YELLOW, &format!("WARNING: {}", title), body);
+    }
+
+    pub fn error(&mut self, title: &str, body: &str) {
+        self.block(RED, &format!("ERROR: {}", title), body);
+    }
+
+    fn block(&mut self, color: &str, title: &str, body: &str) {
+        let mut lines = vec![title.to_string()];
+        if !body.is_empty() {
+            lines.push(String::new());
+            lines.extend(body.lines().map(|line| line.to_string()));
+        }
+
+        for line in lines {
+            let line = format!("{}{}", BLOCK_PREFIX, line);
+            let line = self.paint(color, line.trim_end());
+            self.write_line(&line);
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let _ = writeln!(self.writer, "{}", line);
+        let _ = self.writer.flush();
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Output")
+            .field("color", &self.color)
+            .finish()
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// An in-memory writer whose clones share the same buffer, to inspect what was written through
+/// an `Output`.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(any(test, feature = "testing"))]
+impl SharedBuffer {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_writes_headers_and_steps() {
+        let buffer = SharedBuffer::default();
+        let mut output = Output::from_writer(buffer.clone(), false);
+
+        output.header("Installing Ruby");
+        output.step("Using ruby 2.6.5\nFrom Gemfile.lock");
+
+        assert_eq!(
+            buffer.contents(),
+            "-----> Installing Ruby\n       Using ruby 2.6.5\n       From Gemfile.lock\n"
+        );
+    }
+
+    #[test]
+    fn it_times_steps() {
+        let buffer = SharedBuffer::default();
+        let mut output = Output::from_writer(buffer.clone(), false);
+
+        let result: Result<i32, ()> = output.timed("Installing ruby 2.6.5", || Ok(1));
+        assert_eq!(result, Ok(1));
+        let result: Result<(), &str> = output.timed("Installing bundler", || Err("oops"));
+        assert_eq!(result, Err("oops"));
+
+        assert_eq!(
+            buffer.contents(),
+            "       Installing ruby 2.6.5 ... done (0.0s)\n       Installing bundler ... failed (0.0s)\n"
+        );
+    }
+
+    #[test]
+    fn it_writes_warning_blocks() {
+        let buffer = SharedBuffer::default();
+        let mut output = Output::from_writer(buffer.clone(), false);
+
+        output.warning("No Procfile", "Add a Procfile to define processes");
+
+        assert_eq!(
+            buffer.contents(),
+            " !     WARNING: No Procfile\n !\n !     Add a Procfile to define processes\n"
+        );
+    }
+
+    #[test]
+    fn it_colors_output() {
+        let buffer = SharedBuffer::default();
+        let mut output = Output::from_writer(buffer.clone(), true);
+
+        output.error("Failed", "");
+
+        assert_eq!(buffer.contents(), "\x1b[1;31m !     ERROR: Failed\x1b[0m\n");
+    }
+
+    #[test]
+    fn it_formats_durations() {
+        assert_eq!(format_duration(Duration::from_millis(3200)), "3.2s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+    }
+
+    #[test]
+    fn it_can_be_sent_across_threads() {
+        fn assert_send<T: Send>() {}
+
+        assert_send::<Output>();
+        assert_send::<crate::Build>();
+        assert_send::<crate::Detect>();
+    }
+}
@@ -1,36 +1,76 @@
-use crate::{env::Env, error::Result};
+mod bindings;
+use crate::{
+    env::Env,
+    error::{Operation, Result, ResultExt},
+};
+pub use bindings::{Binding, Bindings};
 use log::debug;
-use std::{fs, path::Path};
+use std::{
+    ffi::OsString,
+    fs,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+};
 
 const ENV_DIR: &str = "env";
+const BINDINGS_DIR: &str = "bindings";
+const SERVICE_BINDING_ROOT_ENV_VAR: &str = "SERVICE_BINDING_ROOT";
+const LEGACY_BINDINGS_ENV_VAR: &str = "CNB_BINDINGS";
 
 #[derive(Debug)]
 pub struct Platform {
     pub env: Env,
+    pub bindings: Bindings,
 }
 
 impl Platform {
     pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Self::with_env(dir, &Env::from_process())
+    }
+
+    /// Reads the platform directory, using `env` instead of the process environment to locate
+    /// the service bindings.
+    pub fn with_env<P: AsRef<Path>>(dir: P, lifecycle_env: &Env) -> Result<Self> {
         let mut env = Env::new();
         let env_dir = dir.as_ref().join(ENV_DIR);
 
         if env_dir.exists() {
-            for entry in env_dir.read_dir()? {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-
-                    // file_stem() returns None if no file name
-                    if let Some(key) = path.file_stem() {
-                        let value = fs::read_to_string(&path)?;
-                        env.set_var(key, value);
-                    }
+            for entry in env_dir
+                .read_dir()
+                .with_context(Operation::ReadDir, &env_dir)?
+            {
+                let path = entry.with_context(Operation::ReadDir, &env_dir)?.path();
+
+                if path.is_dir() || is_hidden(&path) {
+                    debug!("Skipping platform env entry: {}", path.display());
+                    continue;
+                }
+
+                // file_stem() returns None if no file name
+                if let Some(key) = path.file_stem() {
+                    // values are kept as raw bytes, they don't have to be valid unicode
+                    let value = fs::read(&path).with_context(Operation::Read, &path)?;
+                    env.set_var(key, OsString::from_vec(value));
                 }
             }
         }
 
         debug!("Platform environment variables: {:#?}", env);
 
-        Ok(Self { env: env })
+        Ok(Self {
+            env,
+            bindings: Bindings::from_dir(Self::bindings_dir(dir.as_ref(), lifecycle_env))?,
+        })
+    }
+
+    /// `SERVICE_BINDING_ROOT` and the legacy `CNB_BINDINGS` take precedence over
+    /// `<platform>/bindings`.
+    fn bindings_dir(platform_dir: &Path, lifecycle_env: &Env) -> PathBuf {
+        lifecycle_env
+            .var_os(SERVICE_BINDING_ROOT_ENV_VAR)
+            .or_else(|| lifecycle_env.var_os(LEGACY_BINDINGS_ENV_VAR))
+            .map(PathBuf::from)
+            .unwrap_or_else(|| platform_dir.join(BINDINGS_DIR))
     }
 
     pub fn set_env(&self) {
@@ -40,6 +80,13 @@ impl Platform {
     }
 }
 
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with('.'))
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,8 +108,8 @@ mod tests {
 
         Ok(Setup {
             _temp_dir: tmp_dir,
-            env_dir: env_dir,
-            platform_dir: platform_dir,
+            env_dir,
+            platform_dir,
         })
     }
 
@@ -84,6 +131,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_reads_non_unicode_env() -> Result<(), Error> {
+        let setup = setup()?;
+        fs::write(setup.env_dir.join("FOO"), b"f\xffo")?;
+
+        let platform = Platform::new(setup.platform_dir)?;
+        assert_eq!(
+            platform.env.var_os("FOO"),
+            Some(OsString::from_vec(b"f\xffo".to_vec()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_directories_and_hidden_files() -> Result<(), Error> {
+        let setup = setup()?;
+        fs::write(setup.env_dir.join("FOO"), "BAR")?;
+        fs::write(setup.env_dir.join(".hidden"), "BAZ")?;
+        fs::create_dir_all(setup.env_dir.join("DIR"))?;
+
+        let platform = Platform::new(setup.platform_dir)?;
+        assert_eq!(platform.env.var("FOO")?, "BAR");
+        assert_eq!(platform.env.var_os(".hidden"), None);
+        assert_eq!(platform.env.var_os("DIR"), None);
+
+        Ok(())
+    }
+
     #[test]
     fn it_doesnt_blow_up_on_no_env_dir() -> Result<(), Error> {
         let tmpdir = TempDir::new("platform")?;
@@ -95,6 +171,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_reads_bindings() -> Result<(), Error> {
+        let setup = setup()?;
+        let binding_dir = setup.platform_dir.join("bindings").join("db");
+        fs::create_dir_all(&binding_dir)?;
+        fs::write(binding_dir.join("type"), "postgresql")?;
+        fs::write(binding_dir.join("url"), "postgres://localhost")?;
+
+        let platform = Platform::new(setup.platform_dir)?;
+        let binding = platform.bindings.find_by_type("postgresql").unwrap();
+        assert_eq!(binding.name, "db");
+        assert_eq!(binding.get("url"), Some("postgres://localhost"));
+
+        Ok(())
+    }
+
     #[test]
     fn it_sets_env() -> Result<(), Error> {
         let setup = setup()?;
@@ -0,0 +1,343 @@
+use super::is_hidden;
+use crate::error::{ErrorKind, Operation, Result, ResultExt};
+use log::debug;
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+const TYPE_FILE: &str = "type";
+const PROVIDER_FILE: &str = "provider";
+// legacy CNB bindings layout: <binding>/metadata/{kind,provider,tags} and <binding>/secret/*
+const LEGACY_METADATA_DIR: &str = "metadata";
+const LEGACY_SECRET_DIR: &str = "secret";
+const LEGACY_KIND_FILE: &str = "kind";
+const LEGACY_TAGS_FILE: &str = "tags";
+
+#[derive(Debug, Default)]
+pub struct Bindings(Vec<Binding>);
+
+impl Deref for Bindings {
+    type Target = Vec<Binding>;
+
+    fn deref(&self) -> &Vec<Binding> {
+        &self.0
+    }
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Bindings(Vec::new())
+    }
+
+    /// Reads every binding found in `dir`, sorted by binding name. A missing directory yields no
+    /// bindings.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut bindings = Vec::new();
+
+        if dir.is_dir() {
+            for entry in fs::read_dir(dir).with_context(Operation::ReadDir, dir)? {
+                let path = entry.with_context(Operation::ReadDir, dir)?.path();
+
+                if path.is_dir() && !is_hidden(&path) {
+                    bindings.push(Binding::from_dir(&path)?);
+                }
+            }
+        }
+        bindings.sort_by(|a, b| a.name.cmp(&b.name));
+
+        debug!("Platform bindings: {:#?}", bindings);
+
+        Ok(Bindings(bindings))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Binding> {
+        self.0.iter().find(|binding| binding.name == name)
+    }
+
+    /// All bindings of the given type. Types are compared case-insensitively.
+    pub fn by_type<'a>(&'a self, r#type: &'a str) -> impl Iterator<Item = &'a Binding> {
+        self.0
+            .iter()
+            .filter(move |binding| binding.r#type.eq_ignore_ascii_case(r#type))
+    }
+
+    /// The first binding of the given type, if any.
+    pub fn find_by_type(&self, r#type: &str) -> Option<&Binding> {
+        self.0
+            .iter()
+            .find(|binding| binding.r#type.eq_ignore_ascii_case(r#type))
+    }
+}
+
+pub struct Binding {
+    pub name: String,
+    pub path: PathBuf,
+    pub r#type: String,
+    pub provider: Option<String>,
+    /// Only populated by the legacy `metadata/tags` file.
+    pub tags: Vec<String>,
+    /// Extra entries from the legacy `metadata` directory.
+    pub metadata: HashMap<String, String>,
+    secret: HashMap<String, Vec<u8>>,
+}
+
+impl Binding {
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let path = dir.as_ref();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| ErrorKind::new_path(path))?
+            .to_string();
+        let metadata_dir = path.join(LEGACY_METADATA_DIR);
+
+        if metadata_dir.is_dir() {
+            let mut metadata = read_entries(&metadata_dir)?
+                .into_iter()
+                .map(|(key, value)| (key, String::from_utf8_lossy(&value).trim().to_string()))
+                .collect::<HashMap<String, String>>();
+            let r#type = metadata
+                .remove(LEGACY_KIND_FILE)
+                .ok_or_else(|| ErrorKind::FileNotFound(metadata_dir.join(LEGACY_KIND_FILE)))?;
+            let provider = metadata.remove(PROVIDER_FILE);
+            let tags = metadata
+                .remove(LEGACY_TAGS_FILE)
+                .map(|tags| tags.lines().map(|tag| tag.trim().to_string()).collect())
+                .unwrap_or_default();
+
+            Ok(Binding {
+                name,
+                path: path.to_path_buf(),
+                r#type,
+                provider,
+                tags,
+                metadata,
+                secret: read_entries(&path.join(LEGACY_SECRET_DIR))?,
+            })
+        } else {
+            let mut secret = read_entries(path)?;
+            let r#type = secret
+                .remove(TYPE_FILE)
+                .map(|value| String::from_utf8_lossy(&value).trim().to_string())
+                .ok_or_else(|| ErrorKind::FileNotFound(path.join(TYPE_FILE)))?;
+            let provider = secret
+                .remove(PROVIDER_FILE)
+                .map(|value| String::from_utf8_lossy(&value).trim().to_string());
+
+            Ok(Binding {
+                name,
+                path: path.to_path_buf(),
+                r#type,
+                provider,
+                tags: Vec::new(),
+                metadata: HashMap::new(),
+                secret,
+            })
+        }
+    }
+
+    /// Secret entry as a string, `None` if it doesn't exist or isn't valid UTF-8.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_bytes(key)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    pub fn get_bytes(&self, key: &str) -> Option<&[u8]> {
+        self.secret.get(key).map(|value| value.as_slice())
+    }
+
+    /// Names of the secret entries, sorted.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.secret.keys().map(|key| key.as_str()).collect();
+        keys.sort();
+
+        keys
+    }
+}
+
+// secret values must never end up in the debug logs
+impl fmt::Debug for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Binding")
+            .field("name", &self.name)
+            .field("path", &self.path)
+            .field("type", &self.r#type)
+            .field("provider", &self.provider)
+            .field("tags", &self.tags)
+            .field("metadata", &self.metadata)
+            .field("secret", &self.keys())
+            .finish()
+    }
+}
+
+fn read_entries(dir: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let mut entries = HashMap::new();
+
+    if !dir.is_dir() {
+        return Ok(entries);
+    }
+
+    for entry in fs::read_dir(dir).with_context(Operation::ReadDir, dir)? {
+        let path = entry.with_context(Operation::ReadDir, dir)?.path();
+
+        // Kubernetes mounts secrets with hidden `..data` directories and symlinks
+        if path.is_dir() || is_hidden(&path) {
+            continue;
+        }
+
+        if let Some(key) = path.file_name().and_then(|name| name.to_str()) {
+            entries.insert(
+                key.to_string(),
+                fs::read(&path).with_context(Operation::Read, &path)?,
+            );
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Error;
+    use std::result::Result;
+    use tempdir::TempDir;
+
+    fn write_binding(root: &Path, name: &str, files: &[(&str, &str)]) -> Result<(), Error> {
+        let binding_dir = root.join(name);
+        for (file, contents) in files {
+            let path = binding_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, contents)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_bindings() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("bindings")?;
+        write_binding(
+            tmp_dir.path(),
+            "db",
+            &[
+                ("type", "postgresql\n"),
+                ("provider", "heroku"),
+                ("username", "foo"),
+                ("password", "bar"),
+            ],
+        )?;
+        write_binding(
+            tmp_dir.path(),
+            "apm",
+            &[("type", "NewRelic"), ("license_key", "secret")],
+        )?;
+
+        let bindings = Bindings::from_dir(tmp_dir.path())?;
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[0].name, "apm");
+
+        let db = bindings.get("db").unwrap();
+        assert_eq!(db.r#type, "postgresql");
+        assert_eq!(db.provider, Some("heroku".to_string()));
+        assert_eq!(db.get("username"), Some("foo"));
+        assert_eq!(db.get("password"), Some("bar"));
+        assert_eq!(db.get("type"), None);
+        assert_eq!(db.keys(), vec!["password", "username"]);
+
+        let apm = bindings.find_by_type("newrelic").unwrap();
+        assert_eq!(apm.name, "apm");
+        assert_eq!(apm.provider, None);
+        assert_eq!(bindings.by_type("postgresql").count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_legacy_bindings() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("bindings")?;
+        write_binding(
+            tmp_dir.path(),
+            "db",
+            &[
+                ("metadata/kind", "postgresql"),
+                ("metadata/provider", "heroku"),
+                ("metadata/tags", "primary\nreplica\n"),
+                ("metadata/plan", "standard-0"),
+                ("secret/url", "postgres://localhost"),
+            ],
+        )?;
+
+        let bindings = Bindings::from_dir(tmp_dir.path())?;
+        let db = bindings.get("db").unwrap();
+        assert_eq!(db.r#type, "postgresql");
+        assert_eq!(db.provider, Some("heroku".to_string()));
+        assert_eq!(db.tags, vec!["primary", "replica"]);
+        assert_eq!(db.metadata.get("plan"), Some(&"standard-0".to_string()));
+        assert_eq!(db.get("url"), Some("postgres://localhost"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_hidden_entries() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("bindings")?;
+        write_binding(
+            tmp_dir.path(),
+            "db",
+            &[
+                ("type", "mysql"),
+                ("..data/password", "bar"),
+                (".hidden", ""),
+            ],
+        )?;
+        write_binding(tmp_dir.path(), ".hidden", &[("type", "mysql")])?;
+
+        let bindings = Bindings::from_dir(tmp_dir.path())?;
+        assert_eq!(bindings.len(), 1);
+        assert!(bindings.get("db").unwrap().keys().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_errors_on_missing_type() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("bindings")?;
+        write_binding(tmp_dir.path(), "db", &[("password", "bar")])?;
+
+        assert!(Bindings::from_dir(tmp_dir.path()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_doesnt_blow_up_on_no_bindings_dir() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("bindings")?;
+        let bindings = Bindings::from_dir(tmp_dir.path().join("bindings"))?;
+
+        assert!(bindings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_doesnt_debug_print_secrets() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("bindings")?;
+        write_binding(
+            tmp_dir.path(),
+            "db",
+            &[("type", "mysql"), ("password", "hunter2")],
+        )?;
+
+        let bindings = Bindings::from_dir(tmp_dir.path())?;
+        let output = format!("{:?}", bindings);
+        assert!(output.contains("password"));
+        assert!(!output.contains("hunter2"));
+
+        Ok(())
+    }
+}
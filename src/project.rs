@@ -0,0 +1,286 @@
+//! The app's project descriptor, `project.toml`.
+//!
+//! Both the original layout (`[project]`, `[build]`, `[metadata]`) and the 0.2 schema (`[_]`,
+//! `[io.buildpacks]`, `[_.metadata]`) are supported.
+
+use crate::{
+    env::Env,
+    error::{Operation, Result, ResultExt},
+    metadata::Metadata,
+};
+use log::debug;
+use serde_derive::Deserialize;
+use std::{fs, path::Path};
+use toml::value::Value;
+
+const PROJECT_FILE: &str = "project.toml";
+
+#[derive(Debug, Default)]
+pub struct Project {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub build: BuildConfig,
+    pub metadata: Metadata,
+}
+
+impl Project {
+    pub fn from_file<P: AsRef<Path>>(file: P) -> Result<Self> {
+        let file_path = file.as_ref();
+        let toml_string = fs::read_to_string(file_path).with_context(Operation::Read, file_path)?;
+        let project: ProjectToml =
+            toml::from_str(&toml_string).with_context(Operation::Parse, file_path)?;
+        let project = Self::from(project);
+
+        debug!("Project: {:#?}", project);
+
+        Ok(project)
+    }
+
+    /// Reads project.toml in the app dir. `None` if the app doesn't have one.
+    pub fn from_dir<P: AsRef<Path>>(app_dir: P) -> Result<Option<Self>> {
+        let file_path = app_dir.as_ref().join(PROJECT_FILE);
+        if !file_path.is_file() {
+            debug!("No {} found", file_path.display());
+            return Ok(None);
+        }
+
+        Self::from_file(file_path).map(Some)
+    }
+
+    /// The `[[build.env]]` vars.
+    pub fn env(&self) -> Env {
+        let mut env = Env::new();
+        for var in &self.build.env {
+            env.set_var(&var.name, &var.value);
+        }
+
+        env
+    }
+
+    /// The buildpack's own configuration from `[_.metadata.<buildpack id>]`.
+    pub fn buildpack_metadata(&self, buildpack_id: &str) -> Option<&Value> {
+        self.metadata.get(buildpack_id)
+    }
+
+    /// Adds the `[[build.env]]` vars to `env`. Vars already set in `env` take precedence.
+    pub(crate) fn merge_env(&self, env: &mut Env) {
+        for var in &self.build.env {
+            if env.var_os(&var.name).is_none() {
+                env.set_var(&var.name, &var.value);
+            }
+        }
+    }
+}
+
+/// The `[build]` table.
+#[derive(Deserialize, Debug, Default)]
+pub struct BuildConfig {
+    /// Globs of the app files to include, mutually exclusive with `exclude`.
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub buildpacks: Vec<BuildpackRef>,
+    #[serde(default)]
+    pub env: Vec<EnvVar>,
+}
+
+/// A buildpack to use for the build, by id or uri.
+#[derive(Deserialize, Debug)]
+pub struct BuildpackRef {
+    pub id: Option<String>,
+    pub version: Option<String>,
+    pub uri: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct ProjectToml {
+    #[serde(alias = "_")]
+    #[serde(default)]
+    project: ProjectInfo,
+    build: Option<BuildConfig>,
+    #[serde(default)]
+    io: Io,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct ProjectInfo {
+    id: Option<String>,
+    name: Option<String>,
+    version: Option<String>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct Io {
+    buildpacks: Option<IoBuildpacks>,
+}
+
+/// `[io.buildpacks]` in the 0.2 schema.
+#[derive(Deserialize, Default, Debug)]
+struct IoBuildpacks {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    group: Vec<BuildpackRef>,
+    #[serde(default)]
+    build: IoBuild,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct IoBuild {
+    #[serde(default)]
+    env: Vec<EnvVar>,
+}
+
+impl From<IoBuildpacks> for BuildConfig {
+    fn from(io: IoBuildpacks) -> Self {
+        Self {
+            include: io.include,
+            exclude: io.exclude,
+            buildpacks: io.group,
+            env: io.build.env,
+        }
+    }
+}
+
+impl From<ProjectToml> for Project {
+    fn from(project: ProjectToml) -> Self {
+        let ProjectToml {
+            project: info,
+            build,
+            io,
+            mut metadata,
+        } = project;
+        for (key, value) in info.metadata.iter() {
+            metadata.insert(key.as_str(), value.clone());
+        }
+
+        Self {
+            id: info.id,
+            name: info.name,
+            version: info.version,
+            build: build
+                .or_else(|| io.buildpacks.map(BuildConfig::from))
+                .unwrap_or_default(),
+            metadata,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Error;
+    use std::result::Result;
+    use tempdir::TempDir;
+
+    #[test]
+    fn it_reads_project_toml() -> Result<(), Error> {
+        let tmpdir = TempDir::new("project")?;
+        fs::write(
+            tmpdir.path().join("project.toml"),
+            r#"
+[project]
+id = "io.example.app"
+name = "Example"
+version = "1.0.0"
+
+[build]
+exclude = ["spec/**"]
+
+[[build.buildpacks]]
+id = "heroku/ruby"
+version = "1.0.0"
+
+[[build.env]]
+name = "RAILS_ENV"
+value = "production"
+
+[metadata."heroku/ruby"]
+bundle_without = "development:test"
+"#,
+        )?;
+
+        let project = Project::from_dir(tmpdir.path())?.unwrap();
+
+        assert_eq!(project.id.as_deref(), Some("io.example.app"));
+        assert_eq!(project.build.exclude, vec!["spec/**"]);
+        assert_eq!(
+            project.build.buildpacks[0].id.as_deref(),
+            Some("heroku/ruby")
+        );
+        assert_eq!(project.env().var("RAILS_ENV")?, "production");
+        assert_eq!(
+            project.buildpack_metadata("heroku/ruby").unwrap()["bundle_without"].as_str(),
+            Some("development:test")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_schema_0_2() -> Result<(), Error> {
+        let project: ProjectToml = toml::from_str(
+            r#"
+[_]
+schema-version = "0.2"
+id = "io.example.app"
+
+[_.metadata."heroku/ruby"]
+bundle_without = "development:test"
+
+[[io.buildpacks.build.env]]
+name = "RAILS_ENV"
+value = "production"
+
+[[io.buildpacks.group]]
+id = "heroku/ruby"
+"#,
+        )?;
+        let project = Project::from(project);
+
+        assert_eq!(project.id.as_deref(), Some("io.example.app"));
+        assert!(project.buildpack_metadata("heroku/ruby").is_some());
+        assert_eq!(project.env().var("RAILS_ENV")?, "production");
+        assert_eq!(
+            project.build.buildpacks[0].id.as_deref(),
+            Some("heroku/ruby")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_merges_env_without_overriding() {
+        let mut project = Project::default();
+        project.build.env.push(EnvVar {
+            name: "RAILS_ENV".to_string(),
+            value: "production".to_string(),
+        });
+        project.build.env.push(EnvVar {
+            name: "RACK_ENV".to_string(),
+            value: "production".to_string(),
+        });
+        let mut env = Env::new();
+        env.set_var("RAILS_ENV", "staging");
+
+        project.merge_env(&mut env);
+
+        assert_eq!(env.var("RAILS_ENV"), Ok("staging".to_string()));
+        assert_eq!(env.var("RACK_ENV"), Ok("production".to_string()));
+    }
+}
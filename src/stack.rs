@@ -1,33 +1,253 @@
-use crate::error::Result;
+use crate::{
+    buildpack,
+    env::Env,
+    error::{ErrorKind, Operation, Result, ResultExt},
+};
 use log::debug;
-use std::ops::Deref;
+use serde_derive::Deserialize;
+use std::{env::VarError, fs, ops::Deref, path::PathBuf};
 
 const ENV_VAR: &str = "CNB_STACK_ID";
+const STACK_PATH_ENV_VAR: &str = "CNB_STACK_PATH";
+const DEFAULT_STACK_PATH: &str = "/cnb/stack.toml";
+const TARGET_OS_ENV_VAR: &str = "CNB_TARGET_OS";
+const TARGET_ARCH_ENV_VAR: &str = "CNB_TARGET_ARCH";
+const TARGET_ARCH_VARIANT_ENV_VAR: &str = "CNB_TARGET_ARCH_VARIANT";
+const TARGET_DISTRO_NAME_ENV_VAR: &str = "CNB_TARGET_DISTRO_NAME";
+const TARGET_DISTRO_VERSION_ENV_VAR: &str = "CNB_TARGET_DISTRO_VERSION";
+const WILDCARD_STACK_ID: &str = "*";
+const BUILD_MIXIN_PREFIX: &str = "build:";
+const RUN_MIXIN_PREFIX: &str = "run:";
 
 #[derive(Debug)]
-pub struct Stack(String);
+pub struct Stack {
+    /// Empty on newer platforms that only provide target information.
+    pub id: String,
+    /// The mixins provided by the stack, `None` if they're unknown. The lifecycle doesn't pass
+    /// them to buildpacks (they're only in the `io.buildpacks.stack.mixins` label of the stack
+    /// images), so they're `None` unless set by the caller.
+    pub mixins: Option<Vec<String>>,
+    pub build_image: Option<String>,
+    pub run_image: Option<String>,
+    pub run_image_mirrors: Vec<String>,
+    pub target: Target,
+}
 
 impl Deref for Stack {
     type Target = String;
 
     fn deref(&self) -> &String {
-        &self.0
+        &self.id
     }
 }
 
 impl Stack {
     pub fn new() -> Result<Self> {
-        let stack = std::env::var(ENV_VAR)?;
+        Self::from_env(&Env::from_process())
+    }
+
+    /// Builds the stack from `CNB_STACK_ID`, `CNB_TARGET_*` and the platform's `stack.toml`.
+    ///
+    /// Older platforms only set `CNB_STACK_ID` and newer ones only set `CNB_TARGET_*`, so each
+    /// is derived from the other when missing. It's an error if neither is available.
+    pub fn from_env(env: &Env) -> Result<Self> {
+        let id = env.var(ENV_VAR).ok();
+        let target = match Target::from_env(env) {
+            Some(target) => target,
+            None => match &id {
+                Some(id) => Target::from_stack_id(id),
+                None => return Err(ErrorKind::Env(VarError::NotPresent).into()),
+            },
+        };
+        let id = id
+            .or_else(|| target.stack_id().map(|id| id.to_string()))
+            .unwrap_or_default();
+
+        let stack_path = env
+            .var_os(STACK_PATH_ENV_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_STACK_PATH));
+        let stack_toml = if stack_path.is_file() {
+            let contents =
+                fs::read_to_string(&stack_path).with_context(Operation::Read, &stack_path)?;
+            toml::from_str(&contents).with_context(Operation::Parse, &stack_path)?
+        } else {
+            StackToml::default()
+        };
+
+        let stack = Stack {
+            id,
+            mixins: None,
+            build_image: stack_toml.build_image.image,
+            run_image: stack_toml.run_image.image,
+            run_image_mirrors: stack_toml.run_image.mirrors,
+            target,
+        };
 
-        debug!("Stack: {}", stack);
+        debug!("Stack: {:#?}", stack);
 
-        Ok(Stack { 0: stack })
+        Ok(stack)
     }
+
+    /// Finds the entry in buildpack.toml `[[stacks]]` matching this stack and checks that every
+    /// mixin it requires is provided. An exact id match wins over a `*` wildcard entry. The mixin
+    /// check is skipped when the stack's mixins are unknown.
+    pub fn validate<'a>(&self, stacks: &'a [buildpack::Stack]) -> Result<&'a buildpack::Stack> {
+        let buildpack_stack = stacks
+            .iter()
+            .find(|stack| stack.id == self.id)
+            .or_else(|| stacks.iter().find(|stack| stack.id == WILDCARD_STACK_ID))
+            .ok_or_else(|| ErrorKind::UnsupportedStack(self.id.clone()))?;
+
+        if self.mixins.is_none() {
+            debug!("Stack mixins are unknown, skipping the mixin check");
+            return Ok(buildpack_stack);
+        }
+        let missing: Vec<String> = buildpack_stack
+            .mixins
+            .iter()
+            .filter(|mixin| !self.has_mixin(mixin))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(ErrorKind::MissingMixins(self.id.clone(), missing).into());
+        }
+
+        Ok(buildpack_stack)
+    }
+
+    /// `build:` and `run:` prefixed mixins are also satisfied by the unprefixed mixin, which is
+    /// present on both images. `false` if the mixins are unknown.
+    pub fn has_mixin(&self, mixin: &str) -> bool {
+        let unprefixed = mixin
+            .strip_prefix(BUILD_MIXIN_PREFIX)
+            .or_else(|| mixin.strip_prefix(RUN_MIXIN_PREFIX));
+
+        self.mixins
+            .iter()
+            .flatten()
+            .any(|provided| provided == mixin || Some(provided.as_str()) == unprefixed)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Target {
+    pub os: String,
+    pub arch: String,
+    pub arch_variant: Option<String>,
+    pub distro: Option<Distro>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Distro {
+    pub name: String,
+    pub version: String,
+}
+
+impl Target {
+    fn from_env(env: &Env) -> Option<Self> {
+        let os = env.var(TARGET_OS_ENV_VAR).ok()?;
+        let arch = env.var(TARGET_ARCH_ENV_VAR).ok()?;
+        let distro = match (
+            env.var(TARGET_DISTRO_NAME_ENV_VAR),
+            env.var(TARGET_DISTRO_VERSION_ENV_VAR),
+        ) {
+            (Ok(name), Ok(version)) => Some(Distro { name, version }),
+            _ => None,
+        };
+
+        Some(Target {
+            os,
+            arch,
+            arch_variant: env.var(TARGET_ARCH_VARIANT_ENV_VAR).ok(),
+            distro,
+        })
+    }
+
+    /// Best effort target for platforms that predate `CNB_TARGET_*`: the OS and architecture
+    /// the buildpack is running on, and the distro for well known stacks.
+    fn from_stack_id(id: &str) -> Self {
+        let distro = STACK_DISTROS
+            .iter()
+            .find(|(stack_id, _, _)| *stack_id == id)
+            .map(|(_, name, version)| Distro {
+                name: name.to_string(),
+                version: version.to_string(),
+            });
+        let arch = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+
+        Target {
+            os: std::env::consts::OS.to_string(),
+            arch: arch.to_string(),
+            arch_variant: None,
+            distro,
+        }
+    }
+
+    fn stack_id(&self) -> Option<&'static str> {
+        let distro = self.distro.as_ref()?;
+
+        STACK_DISTROS
+            .iter()
+            .find(|(stack_id, name, version)| {
+                stack_id.starts_with("io.buildpacks.stacks.")
+                    && *name == distro.name
+                    && *version == distro.version
+            })
+            .map(|(stack_id, _, _)| *stack_id)
+    }
+}
+
+// (stack id, distro name, distro version)
+const STACK_DISTROS: &[(&str, &str, &str)] = &[
+    ("io.buildpacks.stacks.bionic", "ubuntu", "18.04"),
+    ("io.buildpacks.stacks.jammy", "ubuntu", "22.04"),
+    ("heroku-18", "ubuntu", "18.04"),
+    ("heroku-20", "ubuntu", "20.04"),
+    ("heroku-22", "ubuntu", "22.04"),
+];
+
+#[derive(Deserialize, Default, Debug)]
+struct StackToml {
+    #[serde(rename = "build-image")]
+    #[serde(default)]
+    build_image: BuildImage,
+    #[serde(rename = "run-image")]
+    #[serde(default)]
+    run_image: RunImage,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct BuildImage {
+    image: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct RunImage {
+    image: Option<String>,
+    #[serde(default)]
+    mirrors: Vec<String>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use failure::Error;
+    use std::result::Result;
+    use tempdir::TempDir;
+
+    // a stack read from env without a stack.toml
+    fn stack(id: &str) -> Result<Stack, Error> {
+        let mut env = Env::new();
+        env.set_var("CNB_STACK_ID", id);
+        env.set_var("CNB_STACK_PATH", "/does/not/exist");
+
+        Ok(Stack::from_env(&env)?)
+    }
 
     #[test]
     fn it_reads_stack_from_env() {
@@ -47,4 +267,142 @@ mod tests {
             assert_eq!("aspen", *stack);
         }
     }
+
+    #[test]
+    fn it_falls_back_to_target_from_stack_id() -> Result<(), Error> {
+        let stack = stack("io.buildpacks.stacks.bionic")?;
+        assert_eq!(stack.target.os, std::env::consts::OS);
+        assert_eq!(
+            stack.target.distro,
+            Some(Distro {
+                name: "ubuntu".to_string(),
+                version: "18.04".to_string()
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_falls_back_to_stack_id_from_target() -> Result<(), Error> {
+        let mut env = Env::new();
+        env.set_var("CNB_TARGET_OS", "linux");
+        env.set_var("CNB_TARGET_ARCH", "arm64");
+        env.set_var("CNB_TARGET_ARCH_VARIANT", "v8");
+        env.set_var("CNB_TARGET_DISTRO_NAME", "ubuntu");
+        env.set_var("CNB_TARGET_DISTRO_VERSION", "22.04");
+        env.set_var("CNB_STACK_PATH", "/does/not/exist");
+
+        let stack = Stack::from_env(&env)?;
+        assert_eq!(*stack, "io.buildpacks.stacks.jammy");
+        assert_eq!(stack.target.arch, "arm64");
+        assert_eq!(stack.target.arch_variant, Some("v8".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_without_stack_or_target() {
+        let env = Env::new();
+
+        assert!(Stack::from_env(&env).is_err());
+    }
+
+    #[test]
+    fn it_reads_stack_toml() -> Result<(), Error> {
+        let tmp_dir = TempDir::new("stack")?;
+        let stack_path = tmp_dir.path().join("stack.toml");
+        fs::write(
+            &stack_path,
+            r#"
+[build-image]
+image = "heroku/heroku:18-build"
+
+[run-image]
+image = "heroku/heroku:18"
+mirrors = ["gcr.io/heroku/heroku:18"]
+"#,
+        )?;
+        let mut env = Env::new();
+        env.set_var("CNB_STACK_ID", "heroku-18");
+        env.set_var("CNB_STACK_PATH", &stack_path);
+
+        let stack = Stack::from_env(&env)?;
+        assert_eq!(stack.mixins, None);
+        assert_eq!(
+            stack.build_image,
+            Some("heroku/heroku:18-build".to_string())
+        );
+        assert_eq!(stack.run_image, Some("heroku/heroku:18".to_string()));
+        assert_eq!(stack.run_image_mirrors, vec!["gcr.io/heroku/heroku:18"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_validates_against_buildpack_stacks() -> Result<(), Error> {
+        let mut stack = stack("heroku-18")?;
+        stack.mixins = Some(vec!["libpq-dev".to_string()]);
+
+        let mut heroku18 = buildpack::Stack::new("heroku-18");
+        heroku18.mixins.push("libpq-dev".to_string());
+        let stacks = vec![buildpack::Stack::new("heroku-16"), heroku18];
+        assert_eq!(stack.validate(&stacks)?.id, "heroku-18");
+
+        let stacks = vec![buildpack::Stack::new("heroku-16")];
+        assert!(stack.validate(&stacks).is_err());
+
+        let mut heroku18 = buildpack::Stack::new("heroku-18");
+        heroku18.mixins.push("libxml2".to_string());
+        assert!(stack.validate(&[heroku18]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_validates_against_wildcard_stack() -> Result<(), Error> {
+        let mut stack = stack("heroku-18")?;
+        stack.mixins = Some(Vec::new());
+
+        let mut heroku18 = buildpack::Stack::new("heroku-18");
+        heroku18.mixins.push("libxml2".to_string());
+        let stacks = vec![buildpack::Stack::new("*"), heroku18];
+        // the exact match is preferred, so its missing mixin fails validation
+        assert!(stack.validate(&stacks).is_err());
+
+        let stacks = vec![
+            buildpack::Stack::new("heroku-16"),
+            buildpack::Stack::new("*"),
+        ];
+        assert_eq!(stack.validate(&stacks)?.id, "*");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_matches_prefixed_mixins() -> Result<(), Error> {
+        let mut stack = stack("heroku-18")?;
+        assert!(!stack.has_mixin("libpq-dev"));
+        stack.mixins = Some(vec!["libpq-dev".to_string(), "build:make".to_string()]);
+
+        assert!(stack.has_mixin("libpq-dev"));
+        assert!(stack.has_mixin("build:libpq-dev"));
+        assert!(stack.has_mixin("run:libpq-dev"));
+        assert!(stack.has_mixin("build:make"));
+        assert!(!stack.has_mixin("make"));
+        assert!(!stack.has_mixin("run:make"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_mixin_check_when_mixins_are_unknown() -> Result<(), Error> {
+        let stack = stack("heroku-18")?;
+
+        let mut heroku18 = buildpack::Stack::new("heroku-18");
+        heroku18.mixins.push("libpq-dev".to_string());
+        assert_eq!(stack.validate(&[heroku18])?.id, "heroku-18");
+
+        Ok(())
+    }
 }
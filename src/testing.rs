@@ -0,0 +1,398 @@
+//! Simulates the lifecycle running detect and build in a temporary directory, without touching
+//! the process environment, the current directory or stdin.
+
+use crate::{
+    build_plan::BuildPlan,
+    env::Env,
+    error::Result,
+    layers::{Layer, Layers},
+    output::{Output, SharedBuffer},
+    Build, Detect,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tempdir::TempDir;
+
+const BUILDPACK_FILE: &str = "buildpack.toml";
+const BUILD_PLAN_FILE: &str = "plan.toml";
+const LAUNCH_TOML_FILE: &str = "launch.toml";
+const STACK_TOML_FILE: &str = "stack.toml";
+const DEFAULT_STACK_ID: &str = "heroku-18";
+
+/// A fake app, platform, layers and buildpack directory for running detect and build.
+pub struct Harness {
+    _tmp_dir: TempDir,
+    pub app_dir: PathBuf,
+    pub platform_dir: PathBuf,
+    pub layers_dir: PathBuf,
+    pub buildpack_dir: PathBuf,
+    stack_id: String,
+    plan: String,
+}
+
+impl Harness {
+    pub fn new() -> io::Result<Self> {
+        let tmp_dir = TempDir::new("libbuildpack")?;
+        let app_dir = tmp_dir.path().join("app");
+        let platform_dir = tmp_dir.path().join("platform");
+        let layers_dir = tmp_dir.path().join("layers");
+        let buildpack_dir = tmp_dir.path().join("buildpack");
+
+        for dir in &[&app_dir, &platform_dir, &layers_dir, &buildpack_dir] {
+            fs::create_dir_all(dir)?;
+        }
+
+        Ok(Self {
+            _tmp_dir: tmp_dir,
+            app_dir,
+            platform_dir,
+            layers_dir,
+            buildpack_dir,
+            stack_id: DEFAULT_STACK_ID.to_string(),
+            plan: String::new(),
+        })
+    }
+
+    pub fn stack<S: Into<String>>(mut self, id: S) -> Self {
+        self.stack_id = id.into();
+        self
+    }
+
+    /// The build plan TOML the lifecycle passes to detect and build.
+    pub fn plan<S: Into<String>>(mut self, plan: S) -> Self {
+        self.plan = plan.into();
+        self
+    }
+
+    pub fn buildpack_toml(&self, contents: &str) -> io::Result<()> {
+        fs::write(self.buildpack_dir.join(BUILDPACK_FILE), contents)
+    }
+
+    /// The platform's stack.toml describing the build and run images.
+    pub fn stack_toml(&self, contents: &str) -> io::Result<()> {
+        fs::write(self.stack_toml_path(), contents)
+    }
+
+    /// Writes a file relative to the app directory, creating its parent directories.
+    pub fn app_file<P: AsRef<Path>>(&self, path: P, contents: &str) -> io::Result<()> {
+        let path = self.app_dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, contents)
+    }
+
+    pub fn platform_env(&self, key: &str, value: &str) -> io::Result<()> {
+        let env_dir = self.platform_dir.join("env");
+        fs::create_dir_all(&env_dir)?;
+
+        fs::write(env_dir.join(key), value)
+    }
+
+    pub fn detect<F>(&self, f: F) -> Result<DetectResult>
+    where
+        F: FnOnce(&mut Detect) -> Result<i32>,
+    {
+        let plan_path = self.tmp_path().join(BUILD_PLAN_FILE);
+        let _ = fs::remove_file(&plan_path);
+        let buffer = SharedBuffer::default();
+        let mut detect = Detect::builder()
+            .env(self.lifecycle_env())
+            .app_dir(&self.app_dir)
+            .platform_dir(&self.platform_dir)
+            .build_plan(toml::from_str(&self.plan)?)
+            .build_plan_output(&plan_path)
+            .output(Output::from_writer(buffer.clone(), false))
+            .build()?;
+
+        let code = f(&mut detect)?;
+
+        Ok(DetectResult {
+            code,
+            plan: read_toml(&plan_path)?,
+            output: buffer.contents(),
+        })
+    }
+
+    pub fn build<F>(&self, f: F) -> Result<BuildResult>
+    where
+        F: FnOnce(&mut Build) -> Result<i32>,
+    {
+        let plan_path = self.tmp_path().join(BUILD_PLAN_FILE);
+        let _ = fs::remove_file(&plan_path);
+        let buffer = SharedBuffer::default();
+        let mut build = Build::builder()
+            .env(self.lifecycle_env())
+            .app_dir(&self.app_dir)
+            .buildpack_dir(&self.buildpack_dir)
+            .layers_dir(&self.layers_dir)
+            .platform_dir(&self.platform_dir)
+            .build_plan(toml::from_str(&self.plan)?)
+            .build_plan_output(&plan_path)
+            .output(Output::from_writer(buffer.clone(), false))
+            .build()?;
+
+        let code = f(&mut build)?;
+
+        Ok(BuildResult {
+            code,
+            plan: read_toml(&plan_path)?,
+            launch: read_toml(&self.layers_dir.join(LAUNCH_TOML_FILE))?,
+            layers_dir: self.layers_dir.clone(),
+            output: buffer.contents(),
+        })
+    }
+
+    /// The env the lifecycle would run detect and build with.
+    fn lifecycle_env(&self) -> Env {
+        let mut env = Env::new();
+        env.set_var("CNB_STACK_ID", &self.stack_id);
+        env.set_var("CNB_STACK_PATH", self.stack_toml_path());
+
+        env
+    }
+
+    fn stack_toml_path(&self) -> PathBuf {
+        self.tmp_path().join(STACK_TOML_FILE)
+    }
+
+    fn tmp_path(&self) -> &Path {
+        self._tmp_dir.path()
+    }
+}
+
+#[derive(Debug)]
+pub struct DetectResult {
+    pub code: i32,
+    /// The build plan written by `Detect::pass`, if any.
+    pub plan: Option<BuildPlan>,
+    pub output: String,
+}
+
+#[derive(Debug)]
+pub struct BuildResult {
+    pub code: i32,
+    /// The build plan written by `Build::success`, if any.
+    pub plan: Option<BuildPlan>,
+    pub launch: Option<toml::Value>,
+    pub layers_dir: PathBuf,
+    pub output: String,
+}
+
+impl BuildResult {
+    /// Names of the layers that have metadata written, sorted.
+    pub fn layer_names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+
+        for entry in fs::read_dir(&self.layers_dir)? {
+            let path = entry?.path();
+            if path.extension().map(|ext| ext == "toml").unwrap_or(false)
+                && path.file_name().map(|name| name != LAUNCH_TOML_FILE) == Some(true)
+            {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// A layer as written to disk, with its metadata and env vars read back. `None` if the layer
+    /// doesn't exist.
+    pub fn layer(&self, name: &str) -> Result<Option<Layer>> {
+        if !self.layers_dir.join(name).is_dir() {
+            return Ok(None);
+        }
+
+        let layers = Layers::new(&self.layers_dir);
+        let mut layer = layers.add(name)?;
+        layer.read_metadata()?;
+        layer.read_envs()?;
+
+        Ok(Some(layer))
+    }
+}
+
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if path.is_file() {
+        let contents = fs::read_to_string(path)?;
+        Ok(Some(toml::from_str(&contents)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_plan::Dependency;
+    use failure::Error;
+    use std::result::Result;
+
+    const BUILDPACK_TOML: &str = r#"[buildpack]
+id = "heroku/ruby"
+name = "Heroku Ruby"
+version = "1.0.0"
+
+[[stacks]]
+id = "heroku-18"
+"#;
+
+    #[test]
+    fn it_runs_detect() -> Result<(), Error> {
+        let harness = Harness::new()?.stack("aspen");
+        harness.app_file("Gemfile", "source 'https://rubygems.org'")?;
+
+        let result = harness.detect(|detect| {
+            assert_eq!(*detect.stack, "aspen");
+            if !detect.has_file("Gemfile") {
+                return Ok(detect.fail());
+            }
+
+            let mut build_plan = BuildPlan::new();
+            build_plan.insert("ruby", Dependency::new("2.6.5"));
+            detect.output.header("Ruby detected");
+            detect.pass(Some(&build_plan))
+        })?;
+
+        assert_eq!(result.code, 0);
+        assert_eq!(result.plan.unwrap().get("ruby").unwrap().version, "2.6.5");
+        assert_eq!(result.output, "-----> Ruby detected\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_runs_build() -> Result<(), Error> {
+        let harness = Harness::new()?.plan(
+            r#"
+[ruby]
+version = "2.6.5"
+"#,
+        );
+        harness.buildpack_toml(BUILDPACK_TOML)?;
+        harness.platform_env("RAILS_ENV", "production")?;
+        harness.app_file(
+            "project.toml",
+            r#"
+[[build.env]]
+name = "RAILS_ENV"
+value = "development"
+
+[[build.env]]
+name = "RACK_ENV"
+value = "production"
+"#,
+        )?;
+
+        let result = harness.build(|build| {
+            assert_eq!(build.platform.env.var("RAILS_ENV").unwrap(), "production");
+            assert_eq!(build.platform.env.var("RACK_ENV").unwrap(), "production");
+            let version = build.build_plan.get("ruby").unwrap().version.clone();
+
+            let mut ruby = build.layers.add("ruby")?;
+            ruby.envs
+                .launch
+                .r#override
+                .set_var("RUBY_VERSION", &version);
+            ruby.write_envs()?;
+            ruby.config(|config| config.launch = true)?;
+
+            build
+                .layers
+                .launch
+                .add_process("web", "bundle exec rails s");
+            build.layers.write_launch()?;
+
+            build.success(&build.build_plan)
+        })?;
+
+        assert_eq!(result.code, 0);
+        assert!(result.plan.is_some());
+        assert_eq!(result.layer_names()?, vec!["ruby"]);
+
+        let ruby = result.layer("ruby")?.unwrap();
+        assert!(ruby.config.launch);
+        assert_eq!(
+            ruby.envs.launch.r#override.var("RUBY_VERSION"),
+            Ok("2.6.5".to_string())
+        );
+        assert!(result.layer("node")?.is_none());
+
+        let launch = result.launch.unwrap();
+        assert_eq!(
+            launch["processes"][0]["command"].as_str(),
+            Some("bundle exec rails s")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_inspects_the_app() -> Result<(), Error> {
+        let harness = Harness::new()?;
+        harness.app_file("package.json", r#"{"engines": {"node": "12.x"}}"#)?;
+        harness.app_file(
+            "config/database.yml",
+            "production:\n  adapter: postgresql\n",
+        )?;
+        harness.app_file("src/app/main.csproj", "<Project />")?;
+        harness.app_file("project.toml", "[project]\nid = \"example\"\n")?;
+        harness.app_file("broken.json", "{")?;
+
+        harness.detect(|detect| {
+            assert!(detect.has_glob("**/*.csproj")?);
+            assert!(!detect.has_glob("*.gemspec")?);
+
+            let package: serde_json::Value = detect.read_json("package.json")?.unwrap();
+            assert_eq!(package["engines"]["node"], "12.x");
+            let database: serde_yaml::Value = detect.read_yaml("config/database.yml")?.unwrap();
+            assert_eq!(
+                database["production"]["adapter"].as_str(),
+                Some("postgresql")
+            );
+            let project = detect.project.as_ref().unwrap();
+            assert_eq!(project.id.as_deref(), Some("example"));
+
+            let missing: Option<toml::Value> = detect.read_toml("Cargo.toml")?;
+            assert!(missing.is_none());
+            let broken: crate::error::Result<Option<serde_json::Value>> =
+                detect.read_json("broken.json");
+            assert!(broken
+                .unwrap_err()
+                .to_string()
+                .starts_with("Could not parse"));
+
+            Ok(detect.fail())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_stack_toml() -> Result<(), Error> {
+        let harness = Harness::new()?;
+        harness.stack_toml(
+            r#"
+[run-image]
+image = "heroku/heroku:18"
+"#,
+        )?;
+
+        let result = harness.detect(|detect| {
+            assert_eq!(detect.stack.run_image, Some("heroku/heroku:18".to_string()));
+            assert_eq!(detect.stack.mixins, None);
+            Ok(detect.fail())
+        })?;
+
+        assert_eq!(result.code, 100);
+        assert!(result.plan.is_none());
+
+        Ok(())
+    }
+}